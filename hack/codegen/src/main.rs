@@ -1,7 +1,12 @@
-use std::process;
 use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+};
 use tera::Tera;
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -16,6 +21,21 @@ struct Vehicle {
     propulsion_type: String,
 }
 
+/// What kind of vehicle it is, and where it moves.
+#[derive(Deserialize, Serialize, Debug)]
+struct VehicleCategory {
+    name: String,
+    category: String,
+    domain: String,
+}
+
+/// Tracked vehicle names in one category, for `for_each_vehicle!`.
+#[derive(Serialize, Debug)]
+struct VehicleGroup {
+    category: String,
+    names: Vec<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct VehicleResponse {
     vehicle_list: Vec<Vehicle>,
@@ -32,36 +52,138 @@ struct ClassesResponse {
     loadout_list: Vec<Loadout>,
 }
 
-async fn translators_rs() {
+const CENSUS_ADDR: &str = "https://census.lithafalcon.cc/get/ps2";
+
+const USAGE: &str = "Usage: codegen [--fixtures <dir>] [--output <file>] [--check]
+
+    --fixtures <dir>  Read vehicle.json and loadout.json Census responses from <dir> instead of downloading them
+    --output <file>   Where to write translators.rs (default: lib/catalog/src/translators.rs)
+    --check           Don't write anything, exit with 1 and print what changed if <file> is out of date";
+
+struct Options {
+    fixtures: Option<PathBuf>,
+    output: PathBuf,
+    check: bool,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        fixtures: None,
+        output: PathBuf::from(format!(
+            "{}/../../lib/catalog/src/translators.rs",
+            env!("CARGO_MANIFEST_DIR")
+        )),
+        check: false,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fixtures" => match args.next() {
+                Some(fixtures) => options.fixtures = Some(PathBuf::from(fixtures)),
+                None => usage(),
+            },
+            "--output" => match args.next() {
+                Some(output) => options.output = PathBuf::from(output),
+                None => usage(),
+            },
+            "--check" => options.check = true,
+            _ => usage(),
+        }
+    }
+
+    options
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Gets a Census collection, either from a fixture file (`<dir>/<collection>.json`) or the API.
+async fn census_get<T: serde::de::DeserializeOwned>(
+    fixtures: &Option<PathBuf>,
+    collection: &str,
+) -> T {
+    match fixtures {
+        Some(dir) => {
+            let path = dir.join(format!("{}.json", collection));
+            let raw = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+            serde_json::from_str(&raw)
+                .unwrap_or_else(|e| panic!("failed to parse {}: {}", path.display(), e))
+        }
+        None => reqwest::get(format!("{}/{}", CENSUS_ADDR, collection))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap(),
+    }
+}
+
+/// Every vehicle we track and its category (mbt, light, transport, ...), in the order the
+/// schema lists them. Census names are matched against these and ALIASES.
+const TRACKED_VEHICLES: &[(&str, &str)] = &[
+    ("flash", "light"),
+    ("lightning", "light"),
+    ("harasser", "light"),
+    ("javelin", "light"),
+    ("vanguard", "mbt"),
+    ("prowler", "mbt"),
+    ("magrider", "mbt"),
+    ("chimera", "mbt"),
+    ("sunderer", "transport"),
+    ("galaxy", "transport"),
+    ("valkyrie", "transport"),
+    ("ant", "utility"),
+    ("mosquito", "fighter"),
+    ("reaver", "fighter"),
+    ("scythe", "fighter"),
+    ("liberator", "bomber"),
+    ("dervish", "bomber"),
+    ("corsair", "naval"),
+];
+
+/// Census names for vehicles we count as one of TRACKED_VEHICLES.
+const ALIASES: &[(&str, &str)] = &[
+    ("wasp", "valkyrie"),
+    ("deliverer", "ant"),
+    ("lodestar", "galaxy"),
+];
+
+/// Category for a tracked vehicle name.
+fn vehicle_category(name: &str) -> &'static str {
+    TRACKED_VEHICLES
+        .iter()
+        .find(|(tracked, _)| *tracked == name)
+        .map(|(_, category)| *category)
+        .unwrap_or("unknown")
+}
+
+/// Domain (ground, air, sea) for a tracked vehicle, from Census' propulsion_type.
+/// Census has no propulsion type for water, so boats are the one thing known by name.
+fn vehicle_domain(name: &str, propulsion_type: &str) -> &'static str {
+    match (name, propulsion_type) {
+        ("corsair", _) => "sea",
+        (_, "2") => "air",
+        _ => "ground",
+    }
+}
+
+async fn translators_rs(options: &Options) {
     lazy_static! {
-        static ref ALL_VEHICLES: Vec<&'static str> = vec![
-            "flash",
-            "sunderer",
-            "lightning",
-            "scythe",
-            "vanguard",
-            "prowler",
-            "reaver",
-            "mosquito",
-            "galaxy",
-            "valkyrie",
-            "wasp",
-            "deliverer",
-            "lodestar",
-            "liberator",
-            "ant",
-            "harasser",
-            "dervish",
-            "chimera",
-            "javelin",
-            "corsair",
-            "magrider",
-        ];
-        static ref VEHICLES_RE: Regex =
-            RegexBuilder::new(format!("{}", ALL_VEHICLES.join("|")).as_str())
-                .case_insensitive(true)
-                .build()
-                .unwrap();
+        static ref VEHICLES_RE: Regex = RegexBuilder::new(
+            &TRACKED_VEHICLES
+                .iter()
+                .map(|(name, _)| *name)
+                .chain(ALIASES.iter().map(|(alias, _)| *alias))
+                .collect::<Vec<_>>()
+                .join("|")
+        )
+        .case_insensitive(true)
+        .build()
+        .unwrap();
     }
 
     let mut tera = Tera::default();
@@ -72,12 +194,7 @@ async fn translators_rs() {
     )
     .unwrap();
 
-    let res: VehicleResponse = reqwest::get("https://census.lithafalcon.cc/get/ps2/vehicle")
-        .await
-        .unwrap()
-        .json()
-        .await
-        .unwrap();
+    let res: VehicleResponse = census_get(&options.fixtures, "vehicle").await;
 
     let vehicles: Vec<Vehicle> = res
         .vehicle_list
@@ -109,12 +226,10 @@ async fn translators_rs() {
                 .find(&item.name.as_ref().unwrap().en.as_ref().unwrap())
                 .unwrap();
 
-            let name = matched
-                .as_str()
-                .to_lowercase()
-                .replace("wasp", "valkyrie")
-                .replace("deliverer", "ant")
-                .replace("lodestar", "galaxy");
+            let mut name = matched.as_str().to_lowercase();
+            if let Some((_, tracked)) = ALIASES.iter().find(|(alias, _)| *alias == name) {
+                name = tracked.to_string();
+            }
 
             Vehicle {
                 vehicle_id: item.vehicle_id,
@@ -124,12 +239,25 @@ async fn translators_rs() {
         })
         .collect();
 
-    let res: ClassesResponse = reqwest::get("https://census.lithafalcon.cc/get/ps2/loadout")
-        .await
-        .unwrap()
-        .json()
-        .await
-        .unwrap();
+    // One category per vehicle name, since many IDs share a name
+    let categories: Vec<VehicleCategory> = vehicles
+        .iter()
+        .map(|vehicle| {
+            let name = vehicle.name.as_ref().unwrap().en.as_ref().unwrap();
+            (
+                name.clone(),
+                VehicleCategory {
+                    name: name.clone(),
+                    category: vehicle_category(name).to_string(),
+                    domain: vehicle_domain(name, &vehicle.propulsion_type).to_string(),
+                },
+            )
+        })
+        .collect::<BTreeMap<String, VehicleCategory>>()
+        .into_values()
+        .collect();
+
+    let res: ClassesResponse = census_get(&options.fixtures, "loadout").await;
 
     let classes: Vec<Loadout> = res
         .loadout_list
@@ -151,25 +279,155 @@ async fn translators_rs() {
         })
         .collect();
 
+    // What the schema lists: every vehicle Census has, grouped by category in TRACKED_VEHICLES
+    // order, and every class in loadout order
+    let mut vehicle_groups: Vec<VehicleGroup> = Vec::new();
+    for (name, category) in TRACKED_VEHICLES {
+        if !categories.iter().any(|vehicle| vehicle.name == *name) {
+            continue;
+        }
+        match vehicle_groups.last_mut() {
+            Some(group) if group.category == *category => group.names.push(name.to_string()),
+            _ => vehicle_groups.push(VehicleGroup {
+                category: category.to_string(),
+                names: vec![name.to_string()],
+            }),
+        }
+    }
+
+    let mut class_names: Vec<String> = Vec::new();
+    for class in &classes {
+        if !class_names.contains(&class.code_name) {
+            class_names.push(class.code_name.clone());
+        }
+    }
+
     let mut context = tera::Context::new();
     context.insert("vehicles", &vehicles);
+    context.insert("categories", &categories);
     context.insert("classes", &classes);
+    context.insert("vehicle_groups", &vehicle_groups);
+    context.insert("class_names", &class_names);
+
+    let rendered = rustfmt(&tera.render("translators.rs", &context).unwrap());
+
+    if options.check {
+        check(&options.output, &rendered);
+        return;
+    }
+
+    std::fs::write(&options.output, rendered).unwrap();
+    println!("Wrote {}", options.output.display());
+}
+
+/// Formats generated code the same way `rustfmt <file>` would.
+fn rustfmt(code: &str) -> String {
+    let mut child = process::Command::new("rustfmt")
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .spawn()
+        .expect("failed to execute rustfmt");
 
-    let rendered = tera.render("translators.rs", &context).unwrap();
-    let path_raw = format!(
-        "{}/../../services/websocket/src/translators.rs",
-        env!("CARGO_MANIFEST_DIR")
-    );
-    let path = std::path::Path::new(path_raw.as_str());
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(code.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    if !output.status.success() {
+        panic!("rustfmt failed on generated code");
+    }
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Pulls the `("key", "value")` or `("key", ("value", "value"))` pairs out of one of the generated maps.
+fn generated_map(code: &str, map: &str) -> BTreeMap<String, String> {
+    lazy_static! {
+        static ref PAIR_RE: Regex = Regex::new(r#"\("([^"]+)", (\("[^)]*\)|"[^"]*")\)"#).unwrap();
+    }
+
+    let start = match code.find(format!("static ref {}", map).as_str()) {
+        Some(start) => start,
+        None => return BTreeMap::new(),
+    };
+    let block = &code[start..];
+    let block = &block[..block.find("]);").unwrap_or(block.len())];
+
+    PAIR_RE
+        .captures_iter(block)
+        .map(|pair| (pair[1].to_string(), pair[2].replace(['(', ')', '"'], "")))
+        .collect()
+}
+
+/// Prints what was added, removed, or renamed between two versions of a map.
+/// Returns how many entries changed.
+fn print_map_diff(
+    label: &str,
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> usize {
+    let mut changes = 0;
+
+    for (id, name) in new {
+        match old.get(id) {
+            None => {
+                println!("  + {} {} => {}", label, id, name);
+                changes += 1;
+            }
+            Some(old_name) if old_name != name => {
+                println!("  ~ {} {} => {} (was {})", label, id, name, old_name);
+                changes += 1;
+            }
+            _ => {}
+        }
+    }
+
+    for (id, name) in old {
+        if !new.contains_key(id) {
+            println!("  - {} {} => {}", label, id, name);
+            changes += 1;
+        }
+    }
+
+    changes
+}
+
+/// Compares freshly generated code to what's on disk, exits with 1 if they differ.
+fn check(path: &Path, rendered: &str) {
+    let current = std::fs::read_to_string(path).unwrap_or_default();
+    if current == rendered {
+        println!("{} is up to date", path.display());
+        return;
+    }
+
+    println!("{} is out of date:", path.display());
+    let mut changes = 0;
+    for (label, map) in [
+        ("vehicle", "VEHICLE_TO_NAME"),
+        ("category", "VEHICLE_TO_CATEGORY"),
+        ("loadout", "LOADOUT_TO_CLASS"),
+    ] {
+        changes += print_map_diff(
+            label,
+            &generated_map(&current, map),
+            &generated_map(rendered, map),
+        );
+    }
+
+    if changes == 0 {
+        println!("  (no data changes, but the generated code differs; was the template changed?)");
+    }
 
-    std::fs::write(path, rendered).unwrap();
-    process::Command::new("rustfmt")
-        .arg(path)
-        .output()
-        .expect("failed to execute process");
+    println!("Run `cargo run --bin codegen` to regenerate.");
+    process::exit(1);
 }
 
 #[tokio::main]
 async fn main() {
-    translators_rs().await;
+    let options = parse_args();
+    translators_rs(&options).await;
 }
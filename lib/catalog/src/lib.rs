@@ -0,0 +1,44 @@
+//! Every world, continent, vehicle and class Saerro knows about.
+//!
+//! Each `for_each_*!` macro hands its list to another macro, which turns it into lookup tables
+//! (in the API's utils.rs), GraphQL shorthand fields (in world.rs, zone.rs, vehicles.rs,
+//! classes.rs), or the worlds each websocket environment subscribes to.
+//! Adding an entry here is all it takes for it to show up everywhere.
+//!
+//! `for_each_vehicle!` and `for_each_class!` are generated into translators.rs by codegen,
+//! from the same Census data as the vehicle and loadout tables.
+
+pub mod translators;
+
+/// `field: world ID, "Display Name", Platform, environment, "description";`
+///
+/// `environment` is the websocket environment (event stream) the world is on.
+#[macro_export]
+macro_rules! for_each_world {
+    ($callback:ident) => {
+        $callback! {
+            connery: 1, "Connery", Pc, pc, "The Connery world in US West on PC";
+            miller: 10, "Miller", Pc, pc, "The Miller world in EU on PC";
+            cobalt: 13, "Cobalt", Pc, pc, "The Cobalt world in EU on PC";
+            emerald: 17, "Emerald", Pc, pc, "The Emerald world in US East on PC";
+            jaeger: 19, "Jaeger", Pc, pc, "The Jaeger world in US East on PC";
+            soltech: 40, "SolTech", Pc, pc, "The SolTech world in Japan on PC";
+            genudine: 1000, "Genudine", Ps4, ps4us, "The Genudine world in US East on PS4";
+            ceres: 2000, "Ceres", Ps4, ps4eu, "The Ceres world in EU on PS4";
+        }
+    };
+}
+
+/// `field: zone ID, "Display Name";`
+#[macro_export]
+macro_rules! for_each_zone {
+    ($callback:ident) => {
+        $callback! {
+            indar: 2, "Indar";
+            hossin: 4, "Hossin";
+            amerish: 6, "Amerish";
+            esamir: 8, "Esamir";
+            oshur: 344, "Oshur";
+        }
+    };
+}
@@ -54,6 +54,27 @@ lazy_static! {
         ("2141", "valkyrie"),
         ("2142", "corsair"),
     ]);
+    static ref VEHICLE_TO_CATEGORY: HashMap<&'static str, (&'static str, &'static str)> =
+        HashMap::from([
+            ("ant", ("utility", "ground")),
+            ("chimera", ("mbt", "ground")),
+            ("corsair", ("naval", "sea")),
+            ("dervish", ("bomber", "air")),
+            ("flash", ("light", "ground")),
+            ("galaxy", ("transport", "air")),
+            ("harasser", ("light", "ground")),
+            ("javelin", ("light", "ground")),
+            ("liberator", ("bomber", "air")),
+            ("lightning", ("light", "ground")),
+            ("magrider", ("mbt", "ground")),
+            ("mosquito", ("fighter", "air")),
+            ("prowler", ("mbt", "ground")),
+            ("reaver", ("fighter", "air")),
+            ("scythe", ("fighter", "air")),
+            ("sunderer", ("transport", "ground")),
+            ("valkyrie", ("transport", "air")),
+            ("vanguard", ("mbt", "ground")),
+        ]);
     static ref LOADOUT_TO_CLASS: HashMap<&'static str, &'static str> = HashMap::from([
         ("1", "infiltrator"),
         ("3", "light_assault"),
@@ -89,9 +110,58 @@ pub fn vehicle_to_name(vehicle_id: &str) -> String {
     }
 }
 
+/// Category (mbt, light, transport, utility, fighter, bomber, naval) of a vehicle, by name.
+pub fn vehicle_to_category(vehicle_name: &str) -> String {
+    match VEHICLE_TO_CATEGORY.get(&vehicle_name) {
+        Some((category, _)) => category.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Domain (ground, air, sea) of a vehicle, by name.
+pub fn vehicle_to_domain(vehicle_name: &str) -> String {
+    match VEHICLE_TO_CATEGORY.get(&vehicle_name) {
+        Some((_, domain)) => domain.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
 pub fn loadout_to_class(loadout_id: &str) -> String {
     match LOADOUT_TO_CLASS.get(&loadout_id) {
         Some(name) => name.to_string(),
         None => "unknown".to_string(),
     }
 }
+
+/// Vehicle names as stored in the `vehicle_name` column, grouped by category, in schema order.
+#[macro_export]
+macro_rules! for_each_vehicle {
+    ($callback:ident) => {
+        $callback! {
+            // light
+            flash lightning harasser javelin
+            // mbt
+            vanguard prowler magrider chimera
+            // transport
+            sunderer galaxy valkyrie
+            // utility
+            ant
+            // fighter
+            mosquito reaver scythe
+            // bomber
+            liberator dervish
+            // naval
+            corsair
+        }
+    };
+}
+
+/// Class names as stored in the `class_name` column, in schema order.
+#[macro_export]
+macro_rules! for_each_class {
+    ($callback:ident) => {
+        $callback! {
+            infiltrator light_assault combat_medic engineer heavy_assault max
+        }
+    };
+}
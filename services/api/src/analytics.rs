@@ -1,7 +1,7 @@
-use async_graphql::{futures_util::TryStreamExt, Context, Object, SimpleObject};
-use chrono::{DateTime, Utc};
+use crate::{errors, history::check_buckets, telemetry, timescale::Timescale};
+use async_graphql::{futures_util::TryStreamExt, Context, Object, Result, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
 use sqlx::{query, Pool, Postgres, Row};
-use crate::telemetry;
 
 pub struct Analytics {}
 
@@ -13,56 +13,141 @@ pub struct Event {
     pub count: i64,
 }
 
+/// Where `events` reads from. The rollups are continuous aggregates kept up to date by Timescale,
+/// and they're kept much longer than raw `analytics` rows. Without Timescale they're plain views
+/// over `analytics`, so there's nothing to gain from reading them.
+enum EventSource {
+    Raw,
+    Minutely,
+    Hourly,
+}
+
+impl EventSource {
+    /// Recent ranges up to an hour long are cheap enough to read raw, which is always fresh.
+    /// Otherwise, the coarsest rollup whose buckets evenly divide `bucket_size` is used.
+    fn pick(
+        timescale: &Timescale,
+        bucket_size: u64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Self {
+        if !timescale.0 {
+            return EventSource::Raw;
+        }
+
+        let recent = from > Utc::now() - Duration::hours(1) && to - from <= Duration::hours(1);
+
+        if bucket_size % 3600 == 0 && !recent {
+            EventSource::Hourly
+        } else if bucket_size % 60 == 0 && !recent {
+            EventSource::Minutely
+        } else {
+            EventSource::Raw
+        }
+    }
+
+    /// The table, its time column, and how to add up its rows.
+    fn sql(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            EventSource::Raw => ("analytics", "time", "count(*)"),
+            EventSource::Minutely => ("analytics_1m", "bucket", "sum(count)"),
+            EventSource::Hourly => ("analytics_1h", "bucket", "sum(count)"),
+        }
+    }
+}
+
 #[Object]
 impl Analytics {
-    /// Get all events in analytics, bucket_size is in seconds
+    /// Get all events in analytics, bucket_size is in seconds.
+    /// `from` and `to` default to the last day, or the last hour with `hi_precision`, which also uses 5 second buckets.
+    /// The range can be split into at most 1440 buckets.
     async fn events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
         #[graphql(default = 60)] bucket_size: u64,
         world_id: Option<i32>,
         #[graphql(default = false)] hi_precision: bool,
-    ) -> Vec<Event> {
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Event>> {
         telemetry::graphql_query("Analytics", "events");
-        let pool = ctx.data::<Pool<Postgres>>().unwrap();
+        let pool = ctx.data::<Pool<Postgres>>()?;
 
-        telemetry::db_read("analytics", "events");
-        let sql = format!("
+        let bucket_size = if hi_precision { 5 } else { bucket_size.max(1) };
+        let to = to.unwrap_or_else(Utc::now);
+        let from = from.unwrap_or(if hi_precision {
+            to - Duration::hours(1)
+        } else {
+            to - Duration::days(1)
+        });
+
+        check_buckets(from, to, bucket_size)?;
+
+        let timescale = ctx.data::<Timescale>()?;
+        let (table, time_column, count) = EventSource::pick(timescale, bucket_size, from, to).sql();
+        let world_filter = "AND ($3::integer IS NULL OR world_id = $3)";
+
+        telemetry::db_read(table, "events");
+        let sql = if timescale.0 {
+            format!(
+                "
             SELECT 
-                time_bucket_gapfill('{} seconds', time, start => now() - '{}'::interval, finish => now()) AS bucket, 
-                CASE WHEN count(*) IS NULL THEN 0 ELSE count(*) END AS count, 
+                time_bucket_gapfill('{} seconds', {}, start => $1, finish => $2) AS bucket, 
+                coalesce({}, 0)::bigint AS count, 
                 event_name, 
                 world_id 
-            FROM analytics 
-            WHERE time > now() - '{}'::interval {} 
+            FROM {} 
+            WHERE {} >= $1 AND {} < $2 {} 
             GROUP BY bucket, world_id, event_name 
             ORDER BY bucket ASC",
-            if hi_precision {
-                5
-            } else {
-                bucket_size
-            },
-            if hi_precision {
-                "1 hour"
-            } else {
-                "1 day"
-            },
-            if hi_precision {
-                "1 hour"
-            } else {
-                "1 day"
-            },
-            if let Some(world_id) = world_id {
-                format!("AND world_id = {}", world_id)
-            } else {
-                "".to_string()
-            }
-        );
+                bucket_size, time_column, count, table, time_column, time_column, world_filter,
+            )
+        } else {
+            // What time_bucket_gapfill does: every bucket from `from` to `to`, for each event and world seen in the range
+            let interval = format!("INTERVAL '{} seconds'", bucket_size);
+            format!(
+                "
+            WITH counts AS (
+                SELECT {bucket} AS bucket, event_name, world_id, {count} AS count
+                FROM {table}
+                WHERE {time} >= $1 AND {time} < $2 {world_filter}
+                GROUP BY 1, event_name, world_id
+            ), buckets AS (
+                SELECT bucket FROM generate_series({first}, $2, {interval}) bucket WHERE bucket < $2
+            )
+            SELECT
+                buckets.bucket,
+                coalesce(counts.count, 0)::bigint AS count,
+                groups.event_name,
+                groups.world_id
+            FROM buckets
+            CROSS JOIN (SELECT DISTINCT event_name, world_id FROM counts) groups
+            LEFT JOIN counts ON counts.bucket = buckets.bucket
+                AND counts.event_name = groups.event_name
+                AND counts.world_id = groups.world_id
+            ORDER BY buckets.bucket ASC",
+                bucket = timescale.time_bucket(&interval, time_column),
+                count = count,
+                table = table,
+                time = time_column,
+                world_filter = world_filter,
+                first = timescale.time_bucket(&interval, "$1::timestamptz"),
+                interval = interval,
+            )
+        };
 
-        let mut result = query(sql.as_str()).fetch(pool);
+        let mut result = query(sql.as_str())
+            .bind(from)
+            .bind(to)
+            .bind(world_id)
+            .fetch(pool);
 
         let mut events = Vec::new();
-        while let Some(row) = result.try_next().await.unwrap() {
+        while let Some(row) = result
+            .try_next()
+            .await
+            .map_err(|e| errors::database("analytics/events", e))?
+        {
             events.push(Event {
                 time: row.get("bucket"),
                 event_name: row.get("event_name"),
@@ -71,7 +156,7 @@ impl Analytics {
             });
         }
 
-        events
+        Ok(events)
     }
 }
 
@@ -84,3 +169,62 @@ impl AnalyticsQuery {
         Analytics {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(
+        timescale: bool,
+        bucket_size: u64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> &'static str {
+        EventSource::pick(&Timescale(timescale), bucket_size, from, to)
+            .sql()
+            .0
+    }
+
+    #[test]
+    fn without_timescale_always_reads_raw() {
+        let now = Utc::now();
+        assert_eq!(
+            table(false, 3600, now - Duration::days(7), now),
+            "analytics"
+        );
+        assert_eq!(table(false, 60, now - Duration::days(1), now), "analytics");
+    }
+
+    #[test]
+    fn recent_ranges_read_raw() {
+        let now = Utc::now();
+        assert_eq!(
+            table(true, 60, now - Duration::minutes(30), now),
+            "analytics"
+        );
+        assert_eq!(
+            table(true, 3600, now - Duration::minutes(59), now),
+            "analytics"
+        );
+    }
+
+    #[test]
+    fn long_or_old_ranges_use_the_coarsest_rollup_that_fits() {
+        let now = Utc::now();
+        let day_ago = now - Duration::days(1);
+
+        assert_eq!(table(true, 3600, day_ago, now), "analytics_1h");
+        assert_eq!(table(true, 7200, day_ago, now), "analytics_1h");
+        assert_eq!(table(true, 60, day_ago, now), "analytics_1m");
+        assert_eq!(table(true, 300, day_ago, now), "analytics_1m");
+        assert_eq!(table(true, 90, day_ago, now), "analytics");
+        assert_eq!(table(true, 5, day_ago, now), "analytics");
+
+        // An hour long, but not recent
+        let old = now - Duration::days(3);
+        assert_eq!(
+            table(true, 60, old, old + Duration::minutes(30)),
+            "analytics_1m"
+        );
+    }
+}
@@ -1,48 +1,50 @@
 use crate::{
+    counts::{self, Source},
     factions::{NC, TR, VS},
-    utils::{Filters, IdOrNameBy},
-    telemetry
+    history::{self, HistoryRange, HistorySeries},
+    telemetry,
+    timescale::Timescale,
+    utils::{check_window, default_window, suggestions, unknown, Filters, IdOrNameBy},
 };
-use async_graphql::{Context, Object};
-use sqlx::{Pool, Postgres, Row};
+use async_graphql::{Context, Object, Result};
+use catalog::for_each_class;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+macro_rules! class_names {
+    ($($class:ident)*) => {
+        /// Every class name `class(className: ...)` accepts.
+        const CLASS_NAMES: &[&str] = &[$(stringify!($class)),*];
+    };
+}
+
+for_each_class!(class_names);
 
 /// A specific with optional faction filter.
 pub struct Class {
     filters: Filters,
     class_name: String,
+    /// Minutes since a player was last seen for them to count as active.
+    window: i32,
 }
 
 impl Class {
-    async fn fetch<'ctx>(&self, ctx: &Context<'ctx>, filters: Filters) -> i64 {
-        telemetry::db_read("players", "fetch");
-        let pool = ctx.data::<Pool<Postgres>>().unwrap();
-
-        let sql = format!(
-            "SELECT count(*) FROM players WHERE last_updated > now() - interval '15 minutes' AND class_name = $1 {};",
-            filters.sql(),
-        );
-
-        println!("{}", sql);
-
-        let query: i64 = sqlx::query(sql.as_str())
-            .bind(self.class_name.as_str())
-            .fetch_one(pool)
-            .await
-            .unwrap()
-            .get(0);
-
-        query
+    async fn fetch<'ctx>(&self, ctx: &Context<'ctx>, filters: Filters) -> Result<i64> {
+        counts::count(ctx, Source::Players, self.window, &filters, |group| {
+            group.name == self.class_name
+        })
+        .await
     }
 }
 
 #[Object]
 impl Class {
-    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Class", "total");
 
         self.fetch(ctx, self.filters.clone()).await
     }
-    async fn nc<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn nc<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Class", "nc");
         self.fetch(
             ctx,
@@ -53,7 +55,7 @@ impl Class {
         )
         .await
     }
-    async fn tr<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn tr<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Class", "tr");
         self.fetch(
             ctx,
@@ -64,7 +66,7 @@ impl Class {
         )
         .await
     }
-    async fn vs<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn vs<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Class", "vs");
         self.fetch(
             ctx,
@@ -75,83 +77,123 @@ impl Class {
         )
         .await
     }
+    /// True when Postgres can't be reached and this count is the last one read successfully.
+    async fn stale<'ctx>(&self, ctx: &Context<'ctx>) -> Result<bool> {
+        telemetry::graphql_query("Class", "stale");
+        counts::stale(ctx, Source::Players, self.window).await
+    }
 }
 
 /// Super-struct of each class.
 pub struct Classes {
     filters: Filters,
+    /// Minutes since a player was last seen for them to count as active.
+    window: i32,
 }
 
 impl Classes {
-    pub fn new(filters: Option<Filters>) -> Self {
+    pub fn new(filters: Option<Filters>, window: i32) -> Self {
         Self {
             filters: filters.unwrap_or_default(),
+            window,
         }
     }
 }
 
-#[Object]
-impl Classes {
-    async fn infiltrator(&self) -> Class {
-        telemetry::graphql_query("Classes", "infiltrator");
-        Class {
-            filters: self.filters.clone(),
-            class_name: "infiltrator".to_string(),
-        }
-    }
-    async fn light_assault(&self) -> Class {
-        telemetry::graphql_query("Classes", "light_assault");
-        Class {
-            filters: self.filters.clone(),
-            class_name: "light_assault".to_string(),
-        }
-    }
-    async fn combat_medic(&self) -> Class {
-        telemetry::graphql_query("Classes", "combat_medic");
-        Class {
-            filters: self.filters.clone(),
-            class_name: "combat_medic".to_string(),
-        }
-    }
-    async fn engineer(&self) -> Class {
-        telemetry::graphql_query("Classes", "engineer");
-        Class {
-            filters: self.filters.clone(),
-            class_name: "engineer".to_string(),
-        }
-    }
-    async fn heavy_assault(&self) -> Class {
-        telemetry::graphql_query("Classes", "heavy_assault");
-        Class {
-            filters: self.filters.clone(),
-            class_name: "heavy_assault".to_string(),
-        }
-    }
-    async fn max(&self) -> Class {
-        telemetry::graphql_query("Classes", "max");
-        Class {
-            filters: self.filters.clone(),
-            class_name: "max".to_string(),
+macro_rules! classes_object {
+    ($($class:ident)*) => {
+        #[Object]
+        impl Classes {
+            /// True when Postgres can't be reached and these counts are the last ones read successfully.
+            async fn stale<'ctx>(&self, ctx: &Context<'ctx>) -> Result<bool> {
+                telemetry::graphql_query("Classes", "stale");
+                counts::stale(ctx, Source::Players, self.window).await
+            }
+
+            $(
+                async fn $class(&self) -> Class {
+                    telemetry::graphql_query("Classes", stringify!($class));
+                    Class {
+                        filters: self.filters.clone(),
+                        class_name: stringify!($class).to_string(),
+                        window: self.window,
+                    }
+                }
+            )*
+
+            /// Active players of each class over time, from per-minute snapshots. `filter` defaults to this query's filter,
+            /// `from` and `to` default to the last day, and `bucket` is in seconds (at least 60).
+            async fn history<'ctx>(
+                &self,
+                ctx: &Context<'ctx>,
+                filter: Option<Filters>,
+                from: Option<DateTime<Utc>>,
+                to: Option<DateTime<Utc>>,
+                #[graphql(default = 300)] bucket: u64,
+            ) -> Result<Vec<HistorySeries>> {
+                telemetry::graphql_query("Classes", "history");
+                let filter = filter.unwrap_or_else(|| self.filters.clone());
+                filter.validate()?;
+                let pool = ctx.data::<Pool<Postgres>>()?;
+                let timescale = ctx.data::<Timescale>()?;
+
+                history::series(
+                    pool,
+                    timescale,
+                    "players",
+                    &filter,
+                    &HistoryRange::new(from, to, bucket)?,
+                )
+                .await
+            }
         }
-    }
+    };
 }
 
+for_each_class!(classes_object);
+
 #[derive(Default)]
 pub struct ClassesQuery;
 
 #[Object]
 impl ClassesQuery {
-    /// Get all classes
-    pub async fn classes(&self, filter: Option<Filters>) -> Classes {
-        Classes::new(filter)
+    /// Get all classes. `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    pub async fn classes(
+        &self,
+        filter: Option<Filters>,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Result<Classes> {
+        if let Some(filter) = &filter {
+            filter.validate()?;
+        }
+        Ok(Classes::new(filter, window))
     }
 
-    /// Get a specific class
-    pub async fn class(&self, filter: Option<Filters>, class_name: String) -> Class {
+    /// Get a specific class. `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    pub async fn class(
+        &self,
+        filter: Option<Filters>,
+        class_name: String,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Result<Class> {
         telemetry::graphql_query("Classes", "");
-        Class {
+        if let Some(filter) = &filter {
+            filter.validate()?;
+        }
+        if !CLASS_NAMES.contains(&class_name.as_str()) {
+            return Err(unknown(
+                "class",
+                &class_name,
+                suggestions(&class_name, CLASS_NAMES.iter().copied()),
+            ));
+        }
+
+        Ok(Class {
             filters: filter.unwrap_or_default(),
             class_name,
-        }
+            window,
+        })
     }
 }
@@ -0,0 +1,281 @@
+use crate::{
+    errors::{self, ErrorCode},
+    telemetry,
+    utils::Filters,
+};
+use async_graphql::{
+    async_trait,
+    dataloader::{DataLoader, HashMapCache, Loader},
+    futures_util::{future::try_join_all, TryStreamExt},
+    Context,
+};
+use lazy_static::lazy_static;
+use sqlx::{query, Pool, Postgres, Row};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    /// How long loaded counts are reused across requests, from `COUNTS_CACHE_TTL` in seconds.
+    /// 0 turns reuse off, but the last counts are still kept for when Postgres is down.
+    static ref CACHE_TTL: Duration = Duration::from_secs(
+        std::env::var("COUNTS_CACHE_TTL")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(5)
+    );
+    /// Only keyed by source and window, since every filter is answered from the same groups.
+    static ref CACHE: Mutex<HashMap<CountsKey, Cached>> = Mutex::new(HashMap::new());
+    /// Held while a key is being reloaded, so when its cache expires only one request queries
+    /// Postgres and the rest wait for that result.
+    static ref RELOADING: Mutex<HashMap<CountsKey, Arc<tokio::sync::Mutex<()>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// What active counts are of.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Source {
+    Players,
+    Vehicles,
+    /// Players that are in an outfit, by outfit.
+    Outfits,
+}
+
+/// Everything that's active in one table within `window` minutes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CountsKey {
+    pub source: Source,
+    pub window: i32,
+}
+
+/// How many players or vehicles are active with one combination of world, zone, faction, and so on.
+#[derive(Debug)]
+pub struct Group {
+    pub world_id: i32,
+    pub zone_id: i32,
+    pub faction_id: i32,
+    pub platform: String,
+    /// The class for players, the vehicle for vehicles, or the outfit ID for outfits.
+    pub name: String,
+    /// Only set for vehicles.
+    pub category: Option<String>,
+    /// Only set for vehicles.
+    pub domain: Option<String>,
+    /// Only set for outfits, once their name is resolved.
+    pub outfit_name: Option<String>,
+    /// Only set for outfits, once their tag is resolved.
+    pub outfit_alias: Option<String>,
+    pub count: i64,
+}
+
+/// Active groups for one source and window.
+#[derive(Clone)]
+pub struct Counts {
+    pub groups: Arc<Vec<Group>>,
+    /// Set when Postgres couldn't be read, and these are the last counts that could be.
+    pub stale: bool,
+}
+
+/// The last counts read for a source and window, and when.
+#[derive(Clone)]
+struct Cached {
+    loaded_at: Instant,
+    groups: Arc<Vec<Group>>,
+}
+
+impl Group {
+    pub fn matches(&self, filters: &Filters) -> bool {
+        filters.matches(self.world_id, self.faction_id, self.zone_id, &self.platform)
+    }
+}
+
+/// Loads active counts with one grouped query per source and window, instead of a `count(*)`
+/// per field. A new one is made for each request. Across requests, counts come from a cache
+/// that's at most `CACHE_TTL` old, or any age if Postgres can't be reached.
+pub struct ActiveCounts {
+    pool: Pool<Postgres>,
+}
+
+impl ActiveCounts {
+    pub fn loader(pool: &Pool<Postgres>) -> DataLoader<ActiveCounts, HashMapCache> {
+        DataLoader::with_cache(
+            ActiveCounts { pool: pool.clone() },
+            tokio::spawn,
+            HashMapCache::default(),
+        )
+    }
+
+    async fn query(&self, key: &CountsKey) -> Result<Vec<Group>, sqlx::Error> {
+        let sql = match key.source {
+            Source::Players => {
+                telemetry::db_read("players", "active_counts");
+                "SELECT world_id, zone_id, faction_id, platform, class_name AS name,
+                    NULL::text AS category, NULL::text AS domain,
+                    NULL::text AS outfit_name, NULL::text AS outfit_alias, count(*) AS count
+                FROM players WHERE last_updated > now() - make_interval(mins => $1)
+                GROUP BY world_id, zone_id, faction_id, platform, class_name;"
+            }
+            Source::Vehicles => {
+                telemetry::db_read("vehicles", "active_counts");
+                "SELECT world_id, zone_id, faction_id, platform, vehicle_name AS name,
+                    category, domain, NULL::text AS outfit_name, NULL::text AS outfit_alias,
+                    count(*) AS count
+                FROM vehicles WHERE last_updated > now() - make_interval(mins => $1)
+                GROUP BY world_id, zone_id, faction_id, platform, vehicle_name, category, domain;"
+            }
+            Source::Outfits => {
+                telemetry::db_read("players", "top_outfits");
+                "SELECT world_id, zone_id, faction_id, platform, players.outfit_id AS name,
+                    NULL::text AS category, NULL::text AS domain,
+                    outfits.name AS outfit_name, outfits.alias AS outfit_alias, count(*) AS count
+                FROM players LEFT JOIN outfits ON outfits.outfit_id = players.outfit_id
+                WHERE last_updated > now() - make_interval(mins => $1)
+                    AND players.outfit_id IS NOT NULL
+                GROUP BY world_id, zone_id, faction_id, platform, players.outfit_id,
+                    outfits.name, outfits.alias;"
+            }
+        };
+
+        let mut result = query(sql).bind(key.window).fetch(&self.pool);
+
+        let mut groups = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            groups.push(Group {
+                world_id: row.get("world_id"),
+                zone_id: row.get("zone_id"),
+                faction_id: row.get("faction_id"),
+                platform: row.get("platform"),
+                name: row.get("name"),
+                category: row.get("category"),
+                domain: row.get("domain"),
+                outfit_name: row.get("outfit_name"),
+                outfit_alias: row.get("outfit_alias"),
+                count: row.get("count"),
+            });
+        }
+
+        Ok(groups)
+    }
+
+    /// The cached counts for `key`, if they're younger than CACHE_TTL.
+    fn fresh(key: &CountsKey) -> Option<Counts> {
+        CACHE
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|cached| cached.loaded_at.elapsed() < *CACHE_TTL)
+            .map(|cached| Counts {
+                groups: cached.groups.clone(),
+                stale: false,
+            })
+    }
+
+    async fn counts(&self, key: &CountsKey) -> Result<Counts, Arc<sqlx::Error>> {
+        if let Some(counts) = Self::fresh(key) {
+            telemetry::counts_cache("hit");
+            return Ok(counts);
+        }
+
+        let reloading = RELOADING.lock().unwrap().entry(*key).or_default().clone();
+        let _reloading = reloading.lock().await;
+
+        // Whoever held the lock before may have just reloaded it
+        if let Some(counts) = Self::fresh(key) {
+            telemetry::counts_cache("hit");
+            return Ok(counts);
+        }
+
+        let cached = CACHE.lock().unwrap().get(key).cloned();
+        match self.query(key).await {
+            Ok(groups) => {
+                telemetry::counts_cache("miss");
+                let groups = Arc::new(groups);
+                CACHE.lock().unwrap().insert(
+                    *key,
+                    Cached {
+                        loaded_at: Instant::now(),
+                        groups: groups.clone(),
+                    },
+                );
+                Ok(Counts {
+                    groups,
+                    stale: false,
+                })
+            }
+            Err(e) => {
+                println!("[counts/counts] ERR => {:?}", e);
+                match cached {
+                    Some(cached) => {
+                        telemetry::counts_cache("stale");
+                        Ok(Counts {
+                            groups: cached.groups,
+                            stale: true,
+                        })
+                    }
+                    None => Err(Arc::new(e)),
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<CountsKey> for ActiveCounts {
+    type Value = Counts;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        keys: &[CountsKey],
+    ) -> Result<HashMap<CountsKey, Self::Value>, Self::Error> {
+        let counts = try_join_all(keys.iter().map(|key| self.counts(key))).await?;
+
+        Ok(keys.iter().copied().zip(counts).collect())
+    }
+}
+
+/// Every active group from `source` within `window` minutes, loaded at most once per request.
+/// Errors only when Postgres can't be read and there's nothing cached to fall back on.
+pub async fn counts<'ctx>(
+    ctx: &Context<'ctx>,
+    source: Source,
+    window: i32,
+) -> async_graphql::Result<Counts> {
+    let loader = ctx.data::<DataLoader<ActiveCounts, HashMapCache>>()?;
+
+    // Already logged by `ActiveCounts::counts`
+    loader
+        .load_one(CountsKey { source, window })
+        .await
+        .map_err(|e| errors::error(ErrorCode::of(&e), "Couldn't load active counts"))?
+        .ok_or_else(|| errors::error(ErrorCode::DatabaseError, "Couldn't load active counts"))
+}
+
+/// Adds up active players or vehicles from `source` within `window` minutes that match `filters`
+/// and `include`.
+pub async fn count<'ctx>(
+    ctx: &Context<'ctx>,
+    source: Source,
+    window: i32,
+    filters: &Filters,
+    include: impl Fn(&Group) -> bool,
+) -> async_graphql::Result<i64> {
+    Ok(counts(ctx, source, window)
+        .await?
+        .groups
+        .iter()
+        .filter(|group| group.matches(filters) && include(group))
+        .map(|group| group.count)
+        .sum())
+}
+
+/// Whether counts from `source` within `window` minutes are being served stale.
+pub async fn stale<'ctx>(
+    ctx: &Context<'ctx>,
+    source: Source,
+    window: i32,
+) -> async_graphql::Result<bool> {
+    Ok(counts(ctx, source, window).await?.stale)
+}
@@ -0,0 +1,276 @@
+use crate::telemetry;
+use async_graphql::{Error, ErrorExtensionValues, ErrorExtensions, Response, ServerError, Value};
+use axum::http::StatusCode;
+
+/// What went wrong, sent to clients as `extensions.code` on GraphQL errors, next to
+/// `extensions.retryable`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorCode {
+    /// The query couldn't be parsed or didn't validate, or an argument was out of range.
+    BadRequest,
+    /// A world, faction, zone, or class that isn't known.
+    UnknownFilter,
+    /// A filter the field needs wasn't given.
+    MissingFilter,
+    /// Postgres couldn't be reached in time. Trying again later may work.
+    DatabaseUnavailable,
+    /// Postgres was reached, but the query failed.
+    DatabaseError,
+    /// Anything else that went wrong on our side.
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::UnknownFilter => "UNKNOWN_FILTER",
+            ErrorCode::MissingFilter => "MISSING_FILTER",
+            ErrorCode::DatabaseUnavailable => "DATABASE_UNAVAILABLE",
+            ErrorCode::DatabaseError => "DATABASE_ERROR",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+
+    fn from_str(code: &str) -> Option<Self> {
+        match code {
+            "BAD_REQUEST" => Some(ErrorCode::BadRequest),
+            "UNKNOWN_FILTER" => Some(ErrorCode::UnknownFilter),
+            "MISSING_FILTER" => Some(ErrorCode::MissingFilter),
+            "DATABASE_UNAVAILABLE" => Some(ErrorCode::DatabaseUnavailable),
+            "DATABASE_ERROR" => Some(ErrorCode::DatabaseError),
+            "INTERNAL" => Some(ErrorCode::Internal),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request could work if it's sent again later.
+    pub fn retryable(&self) -> bool {
+        *self == ErrorCode::DatabaseUnavailable
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest | ErrorCode::UnknownFilter | ErrorCode::MissingFilter => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::DatabaseError | ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Connection problems are `DatabaseUnavailable`, anything else `DatabaseError`.
+    pub fn of(e: &sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_) => ErrorCode::DatabaseUnavailable,
+            _ => ErrorCode::DatabaseError,
+        }
+    }
+
+    fn set(&self, extensions: &mut ErrorExtensionValues) {
+        extensions.set("code", self.as_str());
+        extensions.set("retryable", self.retryable());
+    }
+}
+
+/// A GraphQL error with `code` and `retryable` extensions.
+pub fn error(code: ErrorCode, message: impl Into<String>) -> Error {
+    Error::new(message).extend_with(|_, e| code.set(e))
+}
+
+/// Logs a failed query and turns it into a GraphQL error. `source` is where it failed, like
+/// `analytics/events`. The message doesn't include sqlx's, so no SQL reaches clients.
+pub fn database(source: &str, e: sqlx::Error) -> Error {
+    println!("[{}] ERR => {:?}", source, e);
+    let code = ErrorCode::of(&e);
+
+    error(
+        code,
+        if code.retryable() {
+            "The database couldn't be reached, try again shortly"
+        } else {
+            "The database query failed"
+        },
+    )
+}
+
+/// The code for an error that doesn't have one yet. Those are made by async-graphql itself:
+/// parse and validation errors for the whole request (which have no path) and arguments that
+/// don't parse or validate are `BAD_REQUEST`, anything else is `INTERNAL`.
+fn uncoded(error: &ServerError) -> ErrorCode {
+    if error.path.is_empty()
+        || error.message.starts_with("Failed to parse")
+        || error.message.starts_with("Invalid value")
+    {
+        ErrorCode::BadRequest
+    } else {
+        println!("[errors/finish] ERR => {}", error.message);
+        ErrorCode::Internal
+    }
+}
+
+/// Gives every error in `response` a code, counts them, and picks the HTTP status: 200 without
+/// errors, otherwise the highest status of any of them.
+pub fn finish(response: &mut Response) -> StatusCode {
+    let mut status = StatusCode::OK;
+
+    for error in response.errors.iter_mut() {
+        let code = match error.extensions.as_ref().and_then(|e| e.get("code")) {
+            Some(Value::String(code)) => ErrorCode::from_str(code),
+            _ => None,
+        };
+        let code = match code {
+            Some(code) => code,
+            None => {
+                let code = uncoded(error);
+                code.set(error.extensions.get_or_insert_with(Default::default));
+                code
+            }
+        };
+
+        telemetry::error(code.as_str(), "/graphql");
+        status = status.max(code.status());
+    }
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::PathSegment;
+
+    const ALL: [ErrorCode; 6] = [
+        ErrorCode::BadRequest,
+        ErrorCode::UnknownFilter,
+        ErrorCode::MissingFilter,
+        ErrorCode::DatabaseUnavailable,
+        ErrorCode::DatabaseError,
+        ErrorCode::Internal,
+    ];
+
+    fn code_of(error: &ServerError) -> Option<Value> {
+        error
+            .extensions
+            .as_ref()
+            .and_then(|e| e.get("code"))
+            .cloned()
+    }
+
+    fn in_field(message: &str) -> ServerError {
+        let mut error = ServerError::new(message, None);
+        error.path = vec![PathSegment::Field("population".to_string())];
+        error
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        for code in ALL {
+            assert_eq!(ErrorCode::from_str(code.as_str()), Some(code));
+        }
+        assert_eq!(ErrorCode::from_str("NOPE"), None);
+    }
+
+    #[test]
+    fn only_unavailable_is_retryable() {
+        for code in ALL {
+            assert_eq!(code.retryable(), code == ErrorCode::DatabaseUnavailable);
+        }
+    }
+
+    #[test]
+    fn codes_map_to_statuses() {
+        assert_eq!(ErrorCode::BadRequest.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ErrorCode::UnknownFilter.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ErrorCode::MissingFilter.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            ErrorCode::DatabaseUnavailable.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            ErrorCode::DatabaseError.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            ErrorCode::Internal.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn connection_problems_are_unavailable() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+
+        assert_eq!(
+            ErrorCode::of(&sqlx::Error::PoolTimedOut),
+            ErrorCode::DatabaseUnavailable
+        );
+        assert_eq!(
+            ErrorCode::of(&sqlx::Error::PoolClosed),
+            ErrorCode::DatabaseUnavailable
+        );
+        assert_eq!(
+            ErrorCode::of(&sqlx::Error::Io(io)),
+            ErrorCode::DatabaseUnavailable
+        );
+        assert_eq!(
+            ErrorCode::of(&sqlx::Error::RowNotFound),
+            ErrorCode::DatabaseError
+        );
+    }
+
+    #[test]
+    fn database_errors_hide_the_cause() {
+        let e = database("errors/tests", sqlx::Error::PoolTimedOut);
+        assert_eq!(
+            e.message,
+            "The database couldn't be reached, try again shortly"
+        );
+
+        let e = database("errors/tests", sqlx::Error::RowNotFound);
+        assert_eq!(e.message, "The database query failed");
+        assert_eq!(
+            e.extensions.as_ref().and_then(|e| e.get("retryable")),
+            Some(&Value::Boolean(false))
+        );
+    }
+
+    #[test]
+    fn finish_codes_async_graphql_errors() {
+        let mut response = Response::from_errors(vec![
+            ServerError::new("Unknown field \"nope\"", None),
+            in_field("Failed to parse \"Int\": window must be from 1 to 15 minutes"),
+        ]);
+
+        assert_eq!(finish(&mut response), StatusCode::BAD_REQUEST);
+        for error in response.errors.iter() {
+            assert_eq!(code_of(error), Some(Value::from("BAD_REQUEST")));
+        }
+    }
+
+    #[test]
+    fn finish_keeps_codes_and_picks_the_highest_status() {
+        let mut coded = in_field("The database couldn't be reached, try again shortly");
+        ErrorCode::DatabaseUnavailable.set(coded.extensions.get_or_insert_with(Default::default));
+        let mut response = Response::from_errors(vec![
+            ServerError::new("Unknown field \"nope\"", None),
+            coded,
+            in_field("something broke"),
+        ]);
+
+        assert_eq!(finish(&mut response), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            code_of(&response.errors[1]),
+            Some(Value::from("DATABASE_UNAVAILABLE"))
+        );
+        assert_eq!(code_of(&response.errors[2]), Some(Value::from("INTERNAL")));
+    }
+
+    #[test]
+    fn finish_is_ok_without_errors() {
+        assert_eq!(finish(&mut Response::default()), StatusCode::OK);
+    }
+}
@@ -1,8 +1,25 @@
-use crate::{telemetry, utils::ID_TO_WORLD};
+use crate::{
+    telemetry,
+    utils::{Platform, ID_TO_WORLD},
+};
 use async_graphql::{Context, Enum, Object, SimpleObject};
 use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
 use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Deserialize;
 use sqlx::{query, Pool, Postgres, Row};
+use std::time::Duration;
+
+lazy_static! {
+    /// The websocket's healthz, from `WEBSOCKET_HEALTHCHECK`.
+    pub static ref WEBSOCKET_HEALTHCHECK: String = std::env::var("WEBSOCKET_HEALTHCHECK")
+        .unwrap_or("http://127.0.0.1:8999/healthz".to_string());
+    /// Used for every call to the websocket, so a stuck websocket can't hang health checks.
+    pub static ref WEBSOCKET_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .unwrap();
+}
 
 pub async fn get_health(Extension(pool): Extension<Pool<Postgres>>) -> impl IntoResponse {
     telemetry::http_request("/health", "GET");
@@ -18,32 +35,30 @@ pub async fn get_health(Extension(pool): Extension<Pool<Postgres>>) -> impl Into
             let events_row: i64 = row.get(0);
 
             if events_row == 0 {
-                return (
+                (
                     StatusCode::SERVICE_UNAVAILABLE,
                     Json(json!({
                         "websocket": "down",
                         "database": "up"
                     })),
-                );
+                )
             } else {
-                return (
+                (
                     StatusCode::OK,
                     Json(json!({
                         "websocket": "up",
                         "database": "up"
                     })),
-                );
+                )
             }
         }
-        Err(_) => {
-            return (
-                StatusCode::SERVICE_UNAVAILABLE,
-                Json(json!({
-                    "websocket": "down",
-                    "database": "down"
-                })),
-            );
-        }
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "websocket": "down",
+                "database": "down"
+            })),
+        ),
     }
 }
 
@@ -78,14 +93,40 @@ impl Health {
                 let last_event: DateTime<Utc> = row.get(0);
 
                 if last_event < Utc::now() - chrono::Duration::minutes(5) {
-                    return (UpDown::Down, Some(last_event));
+                    (UpDown::Down, Some(last_event))
                 } else {
-                    return (UpDown::Up, Some(last_event));
+                    (UpDown::Up, Some(last_event))
                 }
             }
-            Err(_) => {
-                return (UpDown::Down, None);
+            Err(_) => (UpDown::Down, None),
+        }
+    }
+
+    async fn most_recent_platform_event_time<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        platform: Platform,
+    ) -> (UpDown, Option<DateTime<Utc>>) {
+        let pool = ctx.data::<Pool<Postgres>>().unwrap();
+
+        telemetry::db_read("analytics", "most_recent_platform_event_time");
+        let events_resp =
+            query("SELECT time FROM analytics WHERE platform = $1 ORDER BY time DESC LIMIT 1")
+                .bind(platform.as_str())
+                .fetch_one(pool)
+                .await;
+
+        match events_resp {
+            Ok(row) => {
+                let last_event: DateTime<Utc> = row.get(0);
+
+                if last_event < Utc::now() - chrono::Duration::minutes(5) {
+                    (UpDown::Down, Some(last_event))
+                } else {
+                    (UpDown::Up, Some(last_event))
+                }
             }
+            Err(_) => (UpDown::Down, None),
         }
     }
 }
@@ -141,13 +182,27 @@ impl Health {
     async fn ingest_reachable(&self) -> UpDown {
         telemetry::graphql_query("Health", "ingest_reachable");
 
-        reqwest::get(
-            std::env::var("WEBSOCKET_HEALTHCHECK")
-                .unwrap_or("http://127.0.0.1:8999/healthz".to_string()),
-        )
-        .await
-        .map(|_| UpDown::Up)
-        .unwrap_or(UpDown::Down)
+        WEBSOCKET_CLIENT
+            .get(WEBSOCKET_HEALTHCHECK.as_str())
+            .send()
+            .await
+            .map(|_| UpDown::Up)
+            .unwrap_or(UpDown::Down)
+    }
+
+    /// What the websocket reports about its own connections. Null if it couldn't be reached.
+    async fn ingest_status(&self) -> Option<IngestStatus> {
+        telemetry::graphql_query("Health", "ingest_status");
+
+        // A down websocket answers with 503 but still sends its status, so the status code is ignored.
+        WEBSOCKET_CLIENT
+            .get(WEBSOCKET_HEALTHCHECK.as_str())
+            .send()
+            .await
+            .ok()?
+            .json()
+            .await
+            .ok()
     }
 
     /// Shows a disclaimer for the worlds check
@@ -165,22 +220,103 @@ impl Health {
             worlds.push(WorldUpDown {
                 id: *id,
                 name: name.to_string(),
+                platform: Platform::of_world(*id),
                 status,
                 last_event,
             });
         }
         worlds
     }
+
+    /// Checks if each platform has had any events for the last 5 minutes.
+    /// PC and PS4 are ingested from separate event streams, so one can be down while the other is up.
+    async fn platforms<'ctx>(&self, ctx: &Context<'ctx>) -> Vec<PlatformUpDown> {
+        telemetry::graphql_query("Health", "platforms");
+
+        let mut platforms = Vec::new();
+        for platform in [Platform::Pc, Platform::Ps4] {
+            let (status, last_event) = self.most_recent_platform_event_time(ctx, platform).await;
+            platforms.push(PlatformUpDown {
+                platform,
+                status,
+                last_event,
+            });
+        }
+        platforms
+    }
 }
 
 #[derive(SimpleObject)]
 struct WorldUpDown {
     id: i32,
     name: String,
+    platform: Platform,
     status: UpDown,
     last_event: Option<DateTime<Utc>>,
 }
 
+#[derive(SimpleObject)]
+struct PlatformUpDown {
+    platform: Platform,
+    status: UpDown,
+    last_event: Option<DateTime<Utc>>,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum IngestState {
+    /// Every environment is connected and subscribed
+    Ok,
+
+    /// Some environments are connected and subscribed
+    Degraded,
+
+    /// No environment is connected and subscribed
+    Down,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// One of the websocket's upstream connections, one per environment.
+#[derive(SimpleObject, Deserialize)]
+struct IngestEnvironment {
+    /// pc, ps4us, or ps4eu
+    environment: String,
+    state: ConnectionState,
+    /// The upstream event stream URL, with the service ID redacted
+    upstream: String,
+    connected_since: Option<DateTime<Utc>>,
+    last_message: Option<DateTime<Utc>>,
+    /// Did the event stream acknowledge our subscription?
+    subscribed: bool,
+    /// Messages received but not processed yet
+    queue_depth: i64,
+    last_error: Option<String>,
+}
+
+#[derive(SimpleObject, Deserialize)]
+struct IngestWorld {
+    id: i32,
+    last_event: DateTime<Utc>,
+}
+
+/// The websocket's own view of its condition
+#[derive(SimpleObject, Deserialize)]
+struct IngestStatus {
+    status: IngestState,
+    environments: Vec<IngestEnvironment>,
+    /// When the websocket last processed an event per world
+    worlds: Vec<IngestWorld>,
+    /// Messages received but not processed yet, across all environments
+    queue_depth: i64,
+}
+
 #[derive(Default)]
 pub struct HealthQuery;
 
@@ -0,0 +1,214 @@
+use crate::{
+    errors::{self, ErrorCode},
+    factions::{NC, NSO, TR, VS},
+    telemetry,
+    timescale::Timescale,
+    utils::Filters,
+};
+use async_graphql::{futures_util::TryStreamExt, Result, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{postgres::PgArguments, Arguments, Pool, Postgres, QueryBuilder, Row};
+
+/// History is snapshotted once a minute, so smaller buckets can't hold anything.
+const MIN_BUCKET_SECONDS: u64 = 60;
+
+/// The most buckets one query can ask for, a day of minutes.
+const MAX_BUCKETS: u64 = 1440;
+
+/// Population at one point in time, averaged over the bucket that starts at `time`.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct HistoryPoint {
+    pub time: DateTime<Utc>,
+    pub total: i64,
+    pub nc: i64,
+    pub tr: i64,
+    pub vs: i64,
+    pub ns: i64,
+}
+
+/// The history of one class or vehicle.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct HistorySeries {
+    /// The class or vehicle name, like `heavy_assault` or `sunderer`.
+    pub name: String,
+    pub points: Vec<HistoryPoint>,
+}
+
+/// A time range and bucket size for a history query.
+pub struct HistoryRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub bucket_seconds: u64,
+}
+
+impl HistoryRange {
+    /// `from` and `to` default to the last day, `bucket` is in seconds and at least a minute.
+    /// Fails the same as `check_buckets`.
+    pub fn new(
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        bucket: u64,
+    ) -> Result<Self> {
+        let to = to.unwrap_or_else(Utc::now);
+        let from = from.unwrap_or(to - Duration::days(1));
+        let bucket_seconds = bucket.max(MIN_BUCKET_SECONDS);
+
+        check_buckets(from, to, bucket_seconds)?;
+
+        Ok(Self {
+            from,
+            to,
+            bucket_seconds,
+        })
+    }
+}
+
+/// Fails if `to` isn't after `from`, or splitting the range into `bucket_seconds` buckets
+/// makes more than MAX_BUCKETS of them.
+pub fn check_buckets(from: DateTime<Utc>, to: DateTime<Utc>, bucket_seconds: u64) -> Result<()> {
+    if to <= from {
+        return Err(errors::error(
+            ErrorCode::BadRequest,
+            "`to` must be after `from`",
+        ));
+    }
+
+    let buckets = (to - from).num_seconds() as u64 / bucket_seconds;
+    if buckets > MAX_BUCKETS {
+        return Err(errors::error(
+            ErrorCode::BadRequest,
+            format!(
+                "that's {} buckets, at most {} are allowed. Use a bigger bucket or a shorter range",
+                buckets, MAX_BUCKETS
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads history for `source` (`players` or `vehicles`), split by class or vehicle name when
+/// `by_name` is set. Each bucket's counts are the average of the snapshots taken in it.
+async fn fetch(
+    pool: &Pool<Postgres>,
+    timescale: &Timescale,
+    source: &str,
+    by_name: bool,
+    filters: &Filters,
+    range: &HistoryRange,
+) -> Result<Vec<(String, HistoryPoint)>> {
+    telemetry::db_read("population_history", source);
+
+    let name = if by_name { "name" } else { "''" };
+    let sql = format!(
+        "
+        WITH snapshots AS (
+            SELECT {bucket} AS bucket, count(DISTINCT time) AS snapshots
+            FROM population_history
+            WHERE source = $1 AND time >= $2 AND time < $3
+            GROUP BY bucket
+        ), counts AS (
+            SELECT
+                {bucket} AS bucket,
+                {name} AS name,
+                sum(count) AS total,
+                coalesce(sum(count) FILTER (WHERE faction_id = {nc}), 0) AS nc,
+                coalesce(sum(count) FILTER (WHERE faction_id = {tr}), 0) AS tr,
+                coalesce(sum(count) FILTER (WHERE faction_id = {vs}), 0) AS vs,
+                coalesce(sum(count) FILTER (WHERE faction_id = {ns}), 0) AS ns
+            FROM population_history
+            WHERE source = $1 AND time >= $2 AND time < $3",
+        bucket = timescale.time_bucket("make_interval(secs => $4)", "time"),
+        name = name,
+        nc = NC,
+        tr = TR,
+        vs = VS,
+        ns = NSO,
+    );
+
+    // $1 to $4 are each used more than once, so they're bound up front and the filter's follow
+    let mut arguments = PgArguments::default();
+    arguments.add(source);
+    arguments.add(range.from);
+    arguments.add(range.to);
+    arguments.add(range.bucket_seconds as f64);
+
+    let mut query = QueryBuilder::<Postgres>::with_arguments(sql, arguments);
+    filters.push_sql(&mut query);
+    query.push(
+        "
+            GROUP BY 1, 2
+        )
+        SELECT
+            bucket,
+            name,
+            round(total::numeric / snapshots)::bigint AS total,
+            round(nc::numeric / snapshots)::bigint AS nc,
+            round(tr::numeric / snapshots)::bigint AS tr,
+            round(vs::numeric / snapshots)::bigint AS vs,
+            round(ns::numeric / snapshots)::bigint AS ns
+        FROM counts JOIN snapshots USING (bucket)
+        ORDER BY name, bucket",
+    );
+
+    let mut result = query.build().fetch(pool);
+
+    let mut points = Vec::new();
+    while let Some(row) = result
+        .try_next()
+        .await
+        .map_err(|e| errors::database("history/fetch", e))?
+    {
+        points.push((
+            row.get("name"),
+            HistoryPoint {
+                time: row.get("bucket"),
+                total: row.get("total"),
+                nc: row.get("nc"),
+                tr: row.get("tr"),
+                vs: row.get("vs"),
+                ns: row.get("ns"),
+            },
+        ));
+    }
+
+    Ok(points)
+}
+
+/// Total history for `source`, one point per bucket.
+pub async fn totals(
+    pool: &Pool<Postgres>,
+    timescale: &Timescale,
+    source: &str,
+    filters: &Filters,
+    range: &HistoryRange,
+) -> Result<Vec<HistoryPoint>> {
+    Ok(fetch(pool, timescale, source, false, filters, range)
+        .await?
+        .into_iter()
+        .map(|(_, point)| point)
+        .collect())
+}
+
+/// History for `source` with one series per class or vehicle, sorted by name.
+pub async fn series(
+    pool: &Pool<Postgres>,
+    timescale: &Timescale,
+    source: &str,
+    filters: &Filters,
+    range: &HistoryRange,
+) -> Result<Vec<HistorySeries>> {
+    let mut series: Vec<HistorySeries> = Vec::new();
+
+    for (name, point) in fetch(pool, timescale, source, true, filters, range).await? {
+        match series.last_mut() {
+            Some(last) if last.name == name => last.points.push(point),
+            _ => series.push(HistorySeries {
+                name,
+                points: vec![point],
+            }),
+        }
+    }
+
+    Ok(series)
+}
@@ -0,0 +1,51 @@
+use crate::{
+    counts::{self, Source},
+    utils::Filters,
+};
+use async_graphql::{Context, Result, SimpleObject};
+use std::collections::HashMap;
+
+/// An outfit and how many of its members are currently active.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct OutfitPopulation {
+    /// The outfit ID, as used by Census.
+    pub id: String,
+    /// The outfit's full name. Null if we haven't resolved it yet.
+    pub name: Option<String>,
+    /// The outfit's tag, like `BHO`. Null if we haven't resolved it yet.
+    pub alias: Option<String>,
+    /// Members active within the query's `window`.
+    pub total: i64,
+    /// True when Postgres can't be reached and these counts are the last ones read successfully.
+    pub stale: bool,
+}
+
+/// Top outfits by members active within `window` minutes, filtered the same way as population.
+pub async fn top_outfits<'ctx>(
+    ctx: &Context<'ctx>,
+    filters: &Filters,
+    window: i32,
+    limit: i64,
+) -> Result<Vec<OutfitPopulation>> {
+    let counts = counts::counts(ctx, Source::Outfits, window).await?;
+
+    let mut outfits: HashMap<&str, OutfitPopulation> = HashMap::new();
+    for group in counts.groups.iter().filter(|group| group.matches(filters)) {
+        outfits
+            .entry(&group.name)
+            .or_insert_with(|| OutfitPopulation {
+                id: group.name.clone(),
+                name: group.outfit_name.clone(),
+                alias: group.outfit_alias.clone(),
+                total: 0,
+                stale: counts.stale,
+            })
+            .total += group.count;
+    }
+
+    let mut outfits: Vec<OutfitPopulation> = outfits.into_values().collect();
+    outfits.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.id.cmp(&b.id)));
+    outfits.truncate(limit as usize);
+
+    Ok(outfits)
+}
@@ -1,86 +1,93 @@
 use crate::{
+    counts::{self, Source},
     factions::{NC, NSO, TR, VS},
-    utils::Filters,
+    history::{self, HistoryPoint, HistoryRange},
     telemetry,
+    timescale::Timescale,
+    utils::{check_window, default_window, Filters},
 };
-use async_graphql::{Context, Object};
-use sqlx::{Pool, Postgres, Row};
+use async_graphql::{Context, Object, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
 
 /// A filterable list of currently active players.
 pub struct Population {
     filters: Filters,
+    /// Minutes since a player was last seen for them to count as active.
+    window: i32,
 }
 
 impl Population {
-    pub fn new(filters: Option<Filters>) -> Self {
+    pub fn new(filters: Option<Filters>, window: i32) -> Self {
         Self {
             filters: filters.unwrap_or_default(),
+            window,
         }
     }
 }
 
 impl Population {
-    async fn by_faction<'ctx>(&self, ctx: &Context<'ctx>, faction: i32) -> i64 {
-        let pool = ctx.data::<Pool<Postgres>>().unwrap();
-
-        telemetry::db_read("players", "population_by_faction");
-        let sql = format!(
-            "SELECT count(*) FROM players WHERE last_updated > now() - interval '15 minutes' AND faction_id = $1 {};",
-            self.filters.sql(),
-        );
-
-        println!("{}", sql);
-
-        let query: i64 = sqlx::query(sql.as_str())
-            .bind(faction)
-            .fetch_one(pool)
-            .await
-            .unwrap()
-            .get(0);
-
-        query
+    async fn by_faction<'ctx>(&self, ctx: &Context<'ctx>, faction: i32) -> Result<i64> {
+        counts::count(ctx, Source::Players, self.window, &self.filters, |group| {
+            group.faction_id == faction
+        })
+        .await
     }
 }
 
 #[Object]
 impl Population {
-    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Population", "total");
 
-        let pool = ctx.data::<Pool<Postgres>>().unwrap();
-
-        telemetry::db_read("players", "population_total");
-        let sql = format!(
-            "SELECT count(*) FROM players WHERE last_updated > now() - interval '15 minutes' {};",
-            self.filters.sql(),
-        );
-
-        println!("{}", sql);
-
-        let query: i64 = sqlx::query(sql.as_str())
-            .fetch_one(pool)
-            .await
-            .unwrap()
-            .get(0);
-        
-        query
+        counts::count(ctx, Source::Players, self.window, &self.filters, |_| true).await
     }
-    async fn nc<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn nc<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Population", "nc");
         self.by_faction(ctx, NC).await
     }
-    async fn vs<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn vs<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Population", "vs");
         self.by_faction(ctx, VS).await
     }
-    async fn tr<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn tr<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Population", "tr");
         self.by_faction(ctx, TR).await
     }
-    async fn ns<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn ns<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Population", "ns");
         self.by_faction(ctx, NSO).await
     }
+    /// True when Postgres can't be reached and these counts are the last ones read successfully.
+    async fn stale<'ctx>(&self, ctx: &Context<'ctx>) -> Result<bool> {
+        telemetry::graphql_query("Population", "stale");
+        counts::stale(ctx, Source::Players, self.window).await
+    }
+    /// Population over time, from per-minute snapshots. `filter` defaults to this query's filter,
+    /// `from` and `to` default to the last day, and `bucket` is in seconds (at least 60).
+    async fn history<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        filter: Option<Filters>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        #[graphql(default = 300)] bucket: u64,
+    ) -> Result<Vec<HistoryPoint>> {
+        telemetry::graphql_query("Population", "history");
+        let filter = filter.unwrap_or_else(|| self.filters.clone());
+        filter.validate()?;
+        let pool = ctx.data::<Pool<Postgres>>()?;
+        let timescale = ctx.data::<Timescale>()?;
+
+        history::totals(
+            pool,
+            timescale,
+            "players",
+            &filter,
+            &HistoryRange::new(from, to, bucket)?,
+        )
+        .await
+    }
 }
 
 #[derive(Default)]
@@ -91,7 +98,17 @@ impl PopulationQuery {
     /// A filterable list of currently active players.
     /// This is a core query that others will use to filter by,
     /// i.e. `emerald { population { total } }` is equivalent to `population(filter: { world: { name: "emerald" } }) { total }`
-    pub async fn population(&self, filter: Option<Filters>) -> Population {
-        Population::new(filter)
+    ///
+    /// `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    pub async fn population(
+        &self,
+        filter: Option<Filters>,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Result<Population> {
+        if let Some(filter) = &filter {
+            filter.validate()?;
+        }
+        Ok(Population::new(filter, window))
     }
 }
@@ -1,6 +1,7 @@
 use crate::{
     analytics::AnalyticsQuery, classes::ClassesQuery, health::HealthQuery,
-    population::PopulationQuery, vehicles::VehicleQuery, world::WorldQuery, zone::ZoneQuery,
+    population::PopulationQuery, sessions::SessionsQuery, vehicle_losses::VehicleLossesQuery,
+    vehicles::VehicleQuery, world::WorldQuery, zone::ZoneQuery,
 };
 use async_graphql::MergedObject;
 
@@ -13,4 +14,6 @@ pub struct Query(
     ZoneQuery,
     HealthQuery,
     AnalyticsQuery,
+    VehicleLossesQuery,
+    SessionsQuery,
 );
@@ -0,0 +1,261 @@
+use crate::{
+    errors, telemetry,
+    timescale::Timescale,
+    utils::{interval_minutes, resolve_id, IdOrNameBy, WORLD_IDS},
+};
+use async_graphql::{futures_util::TryStreamExt, Context, Object, Result, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use sqlx::{Pool, Postgres, QueryBuilder, Row};
+use std::collections::HashMap;
+
+lazy_static! {
+    /// How long the websocket waits without activity before closing a session, from the same
+    /// `SESSION_GAP_MINUTES`. Sessions seen more recently than this may still be going.
+    static ref SESSION_GAP_MINUTES: i32 = std::env::var("SESSION_GAP_MINUTES")
+        .unwrap_or("15".to_string())
+        .parse()
+        .unwrap();
+    /// Days of sessions kept, from tasks' `RETENTION_SESSIONS`, and so the most `days` can be.
+    static ref MAX_DAYS: i32 = std::env::var("RETENTION_SESSIONS")
+        .ok()
+        .and_then(|interval| interval_minutes(&interval))
+        .map(|minutes| minutes / (60 * 24))
+        .unwrap_or(30)
+        .max(1);
+}
+
+/// Upper edges (in minutes) of the session length histogram buckets. The last bucket is open-ended.
+const LENGTH_BUCKETS: [i32; 6] = [5, 15, 30, 60, 120, 240];
+
+pub struct Sessions {}
+
+#[derive(SimpleObject, Debug, Clone)]
+pub struct UniquePlayers {
+    /// Start of the day (UTC)
+    pub day: DateTime<Utc>,
+    pub world_id: i32,
+    pub count: i64,
+}
+
+#[derive(SimpleObject, Debug, Clone)]
+pub struct SessionLengthBucket {
+    /// Lower edge of the bucket in minutes, inclusive
+    pub min_minutes: i32,
+    /// Upper edge of the bucket in minutes, exclusive. Null for the last bucket.
+    pub max_minutes: Option<i32>,
+    pub count: i64,
+}
+
+#[derive(SimpleObject, Debug, Clone)]
+pub struct SessionLengths {
+    pub world_id: i32,
+    pub sessions: i64,
+    pub average_minutes: f64,
+    pub median_minutes: f64,
+    pub p90_minutes: f64,
+    pub buckets: Vec<SessionLengthBucket>,
+}
+
+/// The world's ID, or an error if it isn't known.
+fn world_id(world: Option<IdOrNameBy>) -> Result<Option<i32>> {
+    world
+        .map(|world| resolve_id("world", &WORLD_IDS, &world))
+        .transpose()
+}
+
+/// Validates a `days` argument, from 1 up to MAX_DAYS.
+fn check_days(days: &i32) -> Result<(), String> {
+    if (1..=*MAX_DAYS).contains(days) {
+        Ok(())
+    } else {
+        Err(format!("days must be from 1 to {}", *MAX_DAYS))
+    }
+}
+
+/// Leaves out sessions that may still be open.
+fn push_closed(query: &mut QueryBuilder<'_, Postgres>) {
+    query
+        .push(" AND last_seen < now() - make_interval(mins => ")
+        .push_bind(*SESSION_GAP_MINUTES)
+        .push(")");
+}
+
+fn push_world(query: &mut QueryBuilder<'_, Postgres>, world_id: Option<i32>) {
+    if let Some(world_id) = world_id {
+        query.push(" AND world_id = ").push_bind(world_id);
+    }
+}
+
+#[Object]
+impl Sessions {
+    /// Unique characters seen per day per world (a session counts on every day it overlaps), for the last `days` days, up to however many are kept (30 by default).
+    async fn unique_players<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        world: Option<IdOrNameBy>,
+        #[graphql(default = 7, validator(custom = "check_days"))] days: i32,
+    ) -> Result<Vec<UniquePlayers>> {
+        telemetry::graphql_query("Sessions", "unique_players");
+        let world_id = world_id(world)?;
+        let pool = ctx.data::<Pool<Postgres>>()?;
+        let timescale = ctx.data::<Timescale>()?;
+
+        telemetry::db_read("sessions", "unique_players");
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "
+            SELECT
+                day,
+                world_id,
+                count(DISTINCT character_id) AS count
+            FROM sessions, generate_series({}, last_seen, INTERVAL '1 day') AS day
+            WHERE day >= date_trunc('day', now()) - make_interval(days => ",
+            timescale.time_bucket("INTERVAL '1 day'", "first_seen"),
+        ));
+        query.push_bind(days).push(" - 1)");
+        push_world(&mut query, world_id);
+        query.push(
+            "
+            GROUP BY day, world_id
+            ORDER BY day ASC",
+        );
+
+        let mut result = query.build().fetch(pool);
+
+        let mut days = Vec::new();
+        while let Some(row) = result
+            .try_next()
+            .await
+            .map_err(|e| errors::database("sessions/unique_players", e))?
+        {
+            days.push(UniquePlayers {
+                day: row.get("day"),
+                world_id: row.get("world_id"),
+                count: row.get("count"),
+            });
+        }
+
+        Ok(days)
+    }
+
+    /// Session length distribution per world for sessions that ended between `from` and `to`.
+    /// `from` and `to` default to the last day. Sessions still in progress are not counted.
+    async fn lengths<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        world: Option<IdOrNameBy>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SessionLengths>> {
+        telemetry::graphql_query("Sessions", "lengths");
+        let world_id = world_id(world)?;
+        let pool = ctx.data::<Pool<Postgres>>()?;
+
+        let to = to.unwrap_or_else(Utc::now);
+        let from = from.unwrap_or(to - Duration::days(1));
+
+        telemetry::db_read("sessions", "lengths_buckets");
+        let edges: Vec<f64> = LENGTH_BUCKETS.iter().map(|edge| *edge as f64).collect();
+        let mut query = QueryBuilder::<Postgres>::new(
+            "
+            SELECT
+                world_id,
+                width_bucket(extract(epoch FROM last_seen - first_seen)::float8 / 60, ",
+        );
+        query
+            .push_bind(edges)
+            .push(
+                "::float8[]) AS bucket,
+                count(*) AS count
+            FROM sessions
+            WHERE last_seen > ",
+            )
+            .push_bind(from)
+            .push(" AND last_seen <= ")
+            .push_bind(to);
+        push_closed(&mut query);
+        push_world(&mut query, world_id);
+        query.push(" GROUP BY world_id, bucket");
+
+        let mut result = query.build().fetch(pool);
+
+        let mut buckets: HashMap<i32, Vec<i64>> = HashMap::new();
+        while let Some(row) = result
+            .try_next()
+            .await
+            .map_err(|e| errors::database("sessions/lengths", e))?
+        {
+            let world_id: i32 = row.get("world_id");
+            let bucket: i32 = row.get("bucket");
+            let count: i64 = row.get("count");
+
+            buckets
+                .entry(world_id)
+                .or_insert_with(|| vec![0; LENGTH_BUCKETS.len() + 1])[bucket as usize] += count;
+        }
+
+        telemetry::db_read("sessions", "lengths_summary");
+        let mut query = QueryBuilder::<Postgres>::new(
+            "
+            SELECT
+                world_id,
+                count(*) AS sessions,
+                avg(extract(epoch FROM last_seen - first_seen)::float8 / 60) AS average,
+                percentile_cont(0.5) WITHIN GROUP (ORDER BY extract(epoch FROM last_seen - first_seen)::float8 / 60) AS median,
+                percentile_cont(0.9) WITHIN GROUP (ORDER BY extract(epoch FROM last_seen - first_seen)::float8 / 60) AS p90
+            FROM sessions
+            WHERE last_seen > ",
+        );
+        query
+            .push_bind(from)
+            .push(" AND last_seen <= ")
+            .push_bind(to);
+        push_closed(&mut query);
+        push_world(&mut query, world_id);
+        query.push(" GROUP BY world_id ORDER BY world_id ASC");
+
+        let mut result = query.build().fetch(pool);
+
+        let mut lengths = Vec::new();
+        while let Some(row) = result
+            .try_next()
+            .await
+            .map_err(|e| errors::database("sessions/lengths", e))?
+        {
+            let world_id: i32 = row.get("world_id");
+            let counts = buckets
+                .remove(&world_id)
+                .unwrap_or_else(|| vec![0; LENGTH_BUCKETS.len() + 1]);
+
+            lengths.push(SessionLengths {
+                world_id,
+                sessions: row.get("sessions"),
+                average_minutes: row.get("average"),
+                median_minutes: row.get("median"),
+                p90_minutes: row.get("p90"),
+                buckets: counts
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, count)| SessionLengthBucket {
+                        min_minutes: if i == 0 { 0 } else { LENGTH_BUCKETS[i - 1] },
+                        max_minutes: LENGTH_BUCKETS.get(i).copied(),
+                        count,
+                    })
+                    .collect(),
+            });
+        }
+
+        Ok(lengths)
+    }
+}
+
+#[derive(Default)]
+pub struct SessionsQuery;
+
+#[Object]
+impl SessionsQuery {
+    /// Play sessions, opened when a character shows up and closed after they've gone quiet.
+    async fn sessions(&self) -> Sessions {
+        Sessions {}
+    }
+}
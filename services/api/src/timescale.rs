@@ -0,0 +1,36 @@
+use sqlx::{query, Pool, Postgres, Row};
+
+/// Whether the database has TimescaleDB, checked once at startup. Without it, buckets come from
+/// Postgres' own `date_bin` (Postgres 14+), and gaps are filled in with `generate_series`.
+#[derive(Clone, Copy)]
+pub struct Timescale(pub bool);
+
+impl Timescale {
+    pub async fn detect(pool: &Pool<Postgres>) -> Self {
+        let installed: bool =
+            query("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb');")
+                .fetch_one(pool)
+                .await
+                .unwrap()
+                .get(0);
+
+        if !installed {
+            println!("[timescale] TimescaleDB isn't installed, using plain Postgres queries");
+        }
+
+        Self(installed)
+    }
+
+    /// SQL for the start of the `interval`-long bucket that `column` falls in. Buckets line up
+    /// the same either way, since `date_bin` is given Timescale's default origin.
+    pub fn time_bucket(&self, interval: &str, column: &str) -> String {
+        if self.0 {
+            format!("time_bucket({}, {})", interval, column)
+        } else {
+            format!(
+                "date_bin({}, {}, TIMESTAMPTZ '2000-01-03 00:00:00+00')",
+                interval, column
+            )
+        }
+    }
+}
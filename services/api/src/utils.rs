@@ -1,18 +1,55 @@
-use async_graphql::{InputObject, OneofObject};
+use crate::errors::{self, ErrorCode};
+use async_graphql::{Enum, Error, ErrorExtensions, InputObject, OneofObject, Result};
+use catalog::{for_each_world, for_each_zone};
 use lazy_static::lazy_static;
+use sqlx::{Postgres, QueryBuilder};
 use std::collections::HashMap;
 
+macro_rules! world_tables {
+    ($($field:ident: $id:literal, $name:literal, $platform:ident, $environment:ident, $description:tt;)*) => {
+        lazy_static! {
+            pub static ref WORLD_IDS: HashMap<String, i32> = HashMap::from([
+                $((stringify!($field).to_string(), $id),)*
+            ]);
+            /// World names in official game capitalization.
+            pub static ref WORLD_NAMES: HashMap<i32, &'static str> = HashMap::from([
+                $(($id, $name),)*
+            ]);
+        }
+
+        impl Platform {
+            /// The platform a world is played on.
+            pub fn of_world(world_id: i32) -> Platform {
+                match world_id {
+                    $($id => Platform::$platform,)*
+                    _ => Platform::Pc,
+                }
+            }
+        }
+    };
+}
+
+macro_rules! zone_tables {
+    ($($field:ident: $id:literal, $name:literal;)*) => {
+        lazy_static! {
+            pub static ref ZONE_IDS: HashMap<String, i32> = HashMap::from([
+                $((stringify!($field).to_string(), $id),)*
+            ]);
+            /// Zone names in official game capitalization.
+            pub static ref ZONE_NAMES: HashMap<i32, &'static str> = HashMap::from([
+                $(($id, $name),)*
+            ]);
+        }
+    };
+}
+
+for_each_world!(world_tables);
+for_each_zone!(zone_tables);
+
+/// Minutes a player or vehicle counts as active for when `window` isn't given.
+const DEFAULT_WINDOW: i32 = 15;
+
 lazy_static! {
-    pub static ref WORLD_IDS: HashMap<String, i32> = HashMap::from([
-        ("connery".to_string(), 1),
-        ("miller".to_string(), 10),
-        ("cobalt".to_string(), 13),
-        ("emerald".to_string(), 17),
-        ("jaeger".to_string(), 19),
-        ("soltech".to_string(), 40),
-        ("genudine".to_string(), 1000),
-        ("ceres".to_string(), 2000),
-    ]);
     pub static ref ID_TO_WORLD: HashMap<i32, String> = WORLD_IDS
         .iter()
         .map(|(name, id)| (id.to_owned(), name.to_owned()))
@@ -27,17 +64,52 @@ lazy_static! {
         .iter()
         .map(|(name, id)| (id.to_owned(), name.to_owned()))
         .collect();
-    pub static ref ZONE_IDS: HashMap<String, i32> = HashMap::from([
-        ("indar".to_string(), 2),
-        ("hossin".to_string(), 4),
-        ("amerish".to_string(), 6),
-        ("esamir".to_string(), 8),
-        ("oshur".to_string(), 344),
-    ]);
     pub static ref ID_TO_ZONE: HashMap<i32, String> = ZONE_IDS
         .iter()
         .map(|(name, id)| (id.to_owned(), name.to_owned()))
         .collect();
+    /// How many minutes of players and vehicles are kept, and so the biggest `window` there is.
+    /// Read from the same `RETENTION_PLAYERS` and `RETENTION_VEHICLES` that tasks prunes by,
+    /// taking the shorter one.
+    pub static ref MAX_WINDOW: i32 = ["RETENTION_PLAYERS", "RETENTION_VEHICLES"]
+        .iter()
+        .map(|var| match std::env::var(var) {
+            Ok(interval) => interval_minutes(&interval).unwrap_or_else(|| {
+                println!("[utils/MAX_WINDOW] ERR => {} isn't a number of minutes, hours, or days: {}", var, interval);
+                15
+            }),
+            Err(_) => 15,
+        })
+        .min()
+        .unwrap()
+        .max(1);
+}
+
+/// Minutes in a Postgres interval like `15 minutes` or `1 hour`.
+pub fn interval_minutes(interval: &str) -> Option<i32> {
+    let (amount, unit) = interval.trim().split_once(' ')?;
+    let amount: i32 = amount.parse().ok()?;
+    let minutes = match unit.trim().trim_end_matches('s') {
+        "min" | "minute" => 1,
+        "hour" => 60,
+        "day" => 60 * 24,
+        _ => return None,
+    };
+    Some(amount * minutes)
+}
+
+/// The `window` when none is given, 15 minutes, or MAX_WINDOW if less is kept.
+pub fn default_window() -> i32 {
+    DEFAULT_WINDOW.min(*MAX_WINDOW)
+}
+
+/// Validates a `window` argument, from 1 up to MAX_WINDOW minutes.
+pub fn check_window(window: &i32) -> Result<(), String> {
+    if (1..=*MAX_WINDOW).contains(window) {
+        Ok(())
+    } else {
+        Err(format!("window must be from 1 to {} minutes", *MAX_WINDOW))
+    }
 }
 
 /// Allows for one of the following:
@@ -52,14 +124,102 @@ pub enum IdOrNameBy {
 pub fn id_or_name_to_id(map: &HashMap<String, i32>, by: &IdOrNameBy) -> Option<i32> {
     match by {
         IdOrNameBy::Id(id) => Some(*id),
-        IdOrNameBy::Name(name) => map.get(&name.to_lowercase()).map(|id| *id),
+        IdOrNameBy::Name(name) => map.get(&name.to_lowercase()).copied(),
     }
 }
 
-pub fn id_or_name_to_name(map: &HashMap<i32, String>, id: &IdOrNameBy) -> Option<String> {
-    match id {
-        IdOrNameBy::Id(id) => map.get(id).map(|name| name.to_owned()),
-        IdOrNameBy::Name(name) => Some(name.to_owned()),
+/// Like `id_or_name_to_id`, but IDs must be known too, and anything unknown is an error.
+/// `kind` is what's being looked up, like `world`, and is only used in the error.
+pub fn resolve_id(kind: &str, map: &HashMap<String, i32>, by: &IdOrNameBy) -> Result<i32> {
+    match by {
+        IdOrNameBy::Id(id) if map.values().any(|known| known == id) => Ok(*id),
+        IdOrNameBy::Id(id) => Err(unknown(kind, &id.to_string(), Vec::new())),
+        IdOrNameBy::Name(name) => match map.get(&name.to_lowercase()) {
+            Some(id) => Ok(*id),
+            None => Err(unknown(
+                kind,
+                name,
+                suggestions(name, map.keys().map(|key| key.as_str())),
+            )),
+        },
+    }
+}
+
+/// The error for a name or ID that isn't known, with extensions `kind` and `suggestions`
+/// (possibly empty) for clients to show, on top of the usual `code` and `retryable`.
+pub fn unknown(kind: &str, given: &str, suggestions: Vec<String>) -> Error {
+    let message = if suggestions.is_empty() {
+        format!("Unknown {} {:?}", kind, given)
+    } else {
+        format!(
+            "Unknown {} {:?}, did you mean {}?",
+            kind,
+            given,
+            suggestions
+                .iter()
+                .map(|suggestion| format!("{:?}", suggestion))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        )
+    };
+
+    errors::error(ErrorCode::UnknownFilter, message).extend_with(|_, e| {
+        e.set("kind", kind);
+        e.set("suggestions", suggestions);
+    })
+}
+
+/// Known names close enough to `given` to likely be what was meant, closest first.
+pub fn suggestions<'a>(given: &str, known: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let given = given.to_lowercase();
+    // Allow about one typo per three letters
+    let max_distance = (given.chars().count() / 3).max(1);
+
+    let mut close: Vec<(usize, &str)> = known
+        .map(|name| (edit_distance(&given, name), name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    close.sort();
+
+    close
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// Levenshtein distance, counting in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// A gaming platform. PS4 covers both the US (Genudine) and EU (Ceres) PlayStation environments.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Platform {
+    Pc,
+    #[graphql(name = "PS4")]
+    Ps4,
+}
+
+impl Platform {
+    /// The value stored in the `platform` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::Pc => "pc",
+            Platform::Ps4 => "ps4",
+        }
     }
 }
 
@@ -75,26 +235,183 @@ pub struct Filters {
     pub faction: Option<IdOrNameBy>,
     /// The zone or continent to filter by, like Indar, Amerish, etc.
     pub zone: Option<IdOrNameBy>,
+    /// The platform to filter by, PC or PS4
+    pub platform: Option<Platform>,
 }
 
 impl Filters {
-    pub fn sql(&self) -> String {
-        let mut sql = String::new();
+    /// Checks every world, faction, and zone in the filter is known, so a typo is an error
+    /// instead of quietly not filtering.
+    pub fn validate(&self) -> Result<()> {
         if let Some(world) = &self.world {
-            if let Some(world_id) = id_or_name_to_id(&WORLD_IDS, world) {
-                sql.push_str(&format!(" AND world_id = {}", world_id));
-            }
+            resolve_id("world", &WORLD_IDS, world)?;
         }
         if let Some(faction) = &self.faction {
-            if let Some(faction_id) = id_or_name_to_id(&FACTION_IDS, faction) {
-                sql.push_str(&format!(" AND faction_id = {}", faction_id));
-            }
+            resolve_id("faction", &FACTION_IDS, faction)?;
         }
         if let Some(zone) = &self.zone {
-            if let Some(zone_id) = id_or_name_to_id(&ZONE_IDS, zone) {
-                sql.push_str(&format!(" AND zone_id = {}", zone_id));
+            resolve_id("zone", &ZONE_IDS, zone)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an ` AND ...` for each part of the filter, with the values bound. Anything that
+    /// doesn't resolve is left out, so `validate` filters from clients first.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        if let Some(world_id) = self
+            .world
+            .as_ref()
+            .and_then(|world| id_or_name_to_id(&WORLD_IDS, world))
+        {
+            query.push(" AND world_id = ").push_bind(world_id);
+        }
+        if let Some(faction_id) = self
+            .faction
+            .as_ref()
+            .and_then(|faction| id_or_name_to_id(&FACTION_IDS, faction))
+        {
+            query.push(" AND faction_id = ").push_bind(faction_id);
+        }
+        if let Some(zone_id) = self
+            .zone
+            .as_ref()
+            .and_then(|zone| id_or_name_to_id(&ZONE_IDS, zone))
+        {
+            query.push(" AND zone_id = ").push_bind(zone_id);
+        }
+        if let Some(platform) = &self.platform {
+            query.push(" AND platform = ").push_bind(platform.as_str());
+        }
+    }
+
+    /// Whether a row with these values gets through the filter, the same as `push_sql` decides it.
+    pub fn matches(&self, world_id: i32, faction_id: i32, zone_id: i32, platform: &str) -> bool {
+        fn id_matches(map: &HashMap<String, i32>, by: &Option<IdOrNameBy>, id: i32) -> bool {
+            match by.as_ref().and_then(|by| id_or_name_to_id(map, by)) {
+                Some(wanted) => wanted == id,
+                None => true,
             }
         }
-        sql
+
+        id_matches(&WORLD_IDS, &self.world, world_id)
+            && id_matches(&FACTION_IDS, &self.faction, faction_id)
+            && id_matches(&ZONE_IDS, &self.zone, zone_id)
+            && self
+                .platform
+                .map_or(true, |wanted| wanted.as_str() == platform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_extension(e: &Error, key: &str) -> Option<async_graphql::Value> {
+        e.extensions.as_ref().and_then(|ext| ext.get(key)).cloned()
+    }
+
+    #[test]
+    fn interval_minutes_reads_postgres_intervals() {
+        assert_eq!(interval_minutes("15 minutes"), Some(15));
+        assert_eq!(interval_minutes("1 minute"), Some(1));
+        assert_eq!(interval_minutes("30 mins"), Some(30));
+        assert_eq!(interval_minutes(" 2 hours "), Some(120));
+        assert_eq!(interval_minutes("1 day"), Some(60 * 24));
+        assert_eq!(interval_minutes("7 days"), Some(7 * 60 * 24));
+
+        assert_eq!(interval_minutes("15"), None);
+        assert_eq!(interval_minutes("fifteen minutes"), None);
+        assert_eq!(interval_minutes("1 week"), None);
+        assert_eq!(interval_minutes(""), None);
+    }
+
+    #[test]
+    fn check_window_allows_1_to_max_window() {
+        assert!(check_window(&1).is_ok());
+        assert!(check_window(&default_window()).is_ok());
+        assert!(check_window(&MAX_WINDOW).is_ok());
+
+        assert!(check_window(&0).is_err());
+        assert!(check_window(&-5).is_err());
+        assert_eq!(
+            check_window(&(*MAX_WINDOW + 1)),
+            Err(format!("window must be from 1 to {} minutes", *MAX_WINDOW))
+        );
+    }
+
+    #[test]
+    fn resolve_id_accepts_known_ids_and_names() {
+        assert_eq!(
+            resolve_id("world", &WORLD_IDS, &IdOrNameBy::Id(17)).unwrap(),
+            17
+        );
+        assert_eq!(
+            resolve_id(
+                "world",
+                &WORLD_IDS,
+                &IdOrNameBy::Name("EMERALD".to_string())
+            )
+            .unwrap(),
+            17
+        );
+    }
+
+    #[test]
+    fn resolve_id_rejects_unknown_ids_without_suggestions() {
+        let e = resolve_id("world", &WORLD_IDS, &IdOrNameBy::Id(12345)).unwrap_err();
+
+        assert_eq!(e.message, "Unknown world \"12345\"");
+        assert_eq!(
+            error_extension(&e, "code"),
+            Some(async_graphql::Value::from("UNKNOWN_FILTER"))
+        );
+        assert_eq!(
+            error_extension(&e, "kind"),
+            Some(async_graphql::Value::from("world"))
+        );
+        assert_eq!(
+            error_extension(&e, "suggestions"),
+            Some(async_graphql::Value::List(Vec::new()))
+        );
+    }
+
+    #[test]
+    fn resolve_id_suggests_close_names() {
+        let e =
+            resolve_id("world", &WORLD_IDS, &IdOrNameBy::Name("Emerld".to_string())).unwrap_err();
+
+        assert_eq!(
+            e.message,
+            "Unknown world \"Emerld\", did you mean \"emerald\"?"
+        );
+        assert_eq!(
+            error_extension(&e, "suggestions"),
+            Some(async_graphql::Value::List(vec!["emerald".into()]))
+        );
+    }
+
+    #[test]
+    fn suggestions_are_closest_first_and_capped() {
+        let known = ["indigo", "india", "indar", "hossin"];
+        assert_eq!(
+            suggestions("INDA", known.iter().copied()),
+            vec!["indar", "india"]
+        );
+        assert!(suggestions("amerish", known.iter().copied()).is_empty());
+
+        let known = ["ab4", "ab3", "ab2", "ab1"];
+        assert_eq!(
+            suggestions("abc", known.iter().copied()),
+            vec!["ab1", "ab2", "ab3"]
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_characters() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("esamir", "esamir"), 0);
+        assert_eq!(edit_distance("esamir", "esamr"), 1);
+        assert_eq!(edit_distance("oshur", "hossin"), 4);
+        assert_eq!(edit_distance("é", "e"), 1);
     }
 }
@@ -0,0 +1,86 @@
+use crate::{errors, telemetry, utils::Filters};
+use async_graphql::{futures_util::TryStreamExt, Context, Object, Result, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres, QueryBuilder, Row};
+
+/// One cell of the vehicle destruction matrix.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct VehicleLoss {
+    pub world_id: i32,
+    pub zone_id: i32,
+    /// The vehicle that was destroyed, like `sunderer`.
+    pub vehicle_name: String,
+    /// The faction that lost the vehicle.
+    pub faction_id: i32,
+    /// The vehicle the attacker was in, or `infantry` if they were on foot.
+    pub attacker_vehicle_name: String,
+    pub attacker_faction_id: i32,
+    pub count: i64,
+}
+
+#[derive(Default)]
+pub struct VehicleLossesQuery;
+
+#[Object]
+impl VehicleLossesQuery {
+    /// Vehicle destructions grouped by destroyed vehicle, attacker vehicle, factions, and zone.
+    /// `from` and `to` default to the last hour. The faction filter applies to the destroyed vehicle's faction.
+    pub async fn vehicle_losses<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        filter: Option<Filters>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<VehicleLoss>> {
+        telemetry::graphql_query("VehicleLosses", "vehicle_losses");
+        let filter = filter.unwrap_or_default();
+        filter.validate()?;
+        let pool = ctx.data::<Pool<Postgres>>()?;
+
+        let to = to.unwrap_or_else(Utc::now);
+        let from = from.unwrap_or(to - Duration::hours(1));
+
+        telemetry::db_read("vehicle_destroys", "vehicle_losses");
+        let mut query = QueryBuilder::<Postgres>::new(
+            "
+            SELECT
+                world_id,
+                zone_id,
+                vehicle_name,
+                faction_id,
+                attacker_vehicle_name,
+                attacker_faction_id,
+                count(*) AS count
+            FROM vehicle_destroys
+            WHERE time > ",
+        );
+        query.push_bind(from).push(" AND time <= ").push_bind(to);
+        filter.push_sql(&mut query);
+        query.push(
+            "
+            GROUP BY world_id, zone_id, vehicle_name, faction_id, attacker_vehicle_name, attacker_faction_id
+            ORDER BY count DESC",
+        );
+
+        let mut result = query.build().fetch(pool);
+
+        let mut losses = Vec::new();
+        while let Some(row) = result
+            .try_next()
+            .await
+            .map_err(|e| errors::database("vehicle_losses/vehicle_losses", e))?
+        {
+            losses.push(VehicleLoss {
+                world_id: row.get("world_id"),
+                zone_id: row.get("zone_id"),
+                vehicle_name: row.get("vehicle_name"),
+                faction_id: row.get("faction_id"),
+                attacker_vehicle_name: row.get("attacker_vehicle_name"),
+                attacker_faction_id: row.get("attacker_faction_id"),
+                count: row.get("count"),
+            });
+        }
+
+        Ok(losses)
+    }
+}
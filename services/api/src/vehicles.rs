@@ -1,48 +1,63 @@
 use crate::{
+    counts::{self, Group, Source},
     factions::{NC, TR, VS},
-    utils::{Filters, IdOrNameBy},
+    history::{self, HistoryRange, HistorySeries},
     telemetry,
+    timescale::Timescale,
+    utils::{check_window, default_window, Filters, IdOrNameBy},
 };
-use async_graphql::{Context, Object};
-use sqlx::{Pool, Postgres, Row};
+use async_graphql::{Context, Enum, Object, Result, SimpleObject};
+use catalog::for_each_vehicle;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+/// Which vehicles a `Vehicle` counts.
+enum VehicleGroup {
+    /// One vehicle, by its `vehicle_name`.
+    Name(&'static str),
+    /// Every vehicle in a category.
+    Category(VehicleCategory),
+    /// Every vehicle in a domain (ground, air, sea).
+    Domain(&'static str),
+}
+
+impl VehicleGroup {
+    fn contains(&self, group: &Group) -> bool {
+        match self {
+            VehicleGroup::Name(name) => group.name == *name,
+            VehicleGroup::Category(category) => {
+                group.category.as_deref() == Some(category.as_str())
+            }
+            VehicleGroup::Domain(domain) => group.domain.as_deref() == Some(*domain),
+        }
+    }
+}
 
-/// A specific vehicle
+/// A specific vehicle, or a group of vehicles sharing a category or domain
 pub struct Vehicle {
     filters: Filters,
-    vehicle_name: String,
+    vehicles: VehicleGroup,
+    /// Minutes since a vehicle was last seen for it to count as active.
+    window: i32,
 }
 
 impl Vehicle {
-    async fn fetch<'ctx>(&self, ctx: &Context<'ctx>, filters: Filters) -> i64 {
-        let pool = ctx.data::<Pool<Postgres>>().unwrap();
-
-        telemetry::db_read("vehicles", "fetch");
-        let sql = format!(
-            "SELECT count(*) FROM vehicles WHERE last_updated > now() - interval '15 minutes' AND vehicle_name = $1 {};",
-            filters.sql(),
-        );
-
-        println!("{}", sql);
-
-        let query: i64 = sqlx::query(sql.as_str())
-            .bind(self.vehicle_name.as_str())
-            .fetch_one(pool)
-            .await
-            .unwrap()
-            .get(0);
-
-        query
+    async fn fetch<'ctx>(&self, ctx: &Context<'ctx>, filters: Filters) -> Result<i64> {
+        counts::count(ctx, Source::Vehicles, self.window, &filters, |group| {
+            self.vehicles.contains(group)
+        })
+        .await
     }
 }
 
 #[Object]
 impl Vehicle {
-    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Vehicle", "total");
 
         self.fetch(ctx, self.filters.clone()).await
     }
-    async fn nc<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn nc<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Vehicle", "nc");
 
         self.fetch(
@@ -54,7 +69,7 @@ impl Vehicle {
         )
         .await
     }
-    async fn tr<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn tr<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Vehicle", "tr");
 
         self.fetch(
@@ -66,7 +81,7 @@ impl Vehicle {
         )
         .await
     }
-    async fn vs<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
+    async fn vs<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
         telemetry::graphql_query("Vehicle", "vs");
 
         self.fetch(
@@ -78,202 +93,222 @@ impl Vehicle {
         )
         .await
     }
-}
-
-/// Super-struct for all vehicles.
-pub struct Vehicles {
-    filters: Filters,
-}
-
-impl Vehicles {
-    pub fn new(filters: Option<Filters>) -> Self {
-        Self {
-            filters: filters.unwrap_or_default(),
-        }
+    /// True when Postgres can't be reached and this count is the last one read successfully.
+    async fn stale<'ctx>(&self, ctx: &Context<'ctx>) -> Result<bool> {
+        telemetry::graphql_query("Vehicle", "stale");
+        counts::stale(ctx, Source::Vehicles, self.window).await
     }
 }
 
-#[Object]
-impl Vehicles {
-    async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> i64 {
-        telemetry::graphql_query("Vehicles", "total");
-
-        let pool = ctx.data::<Pool<Postgres>>().unwrap();
-
-        telemetry::db_read("players", "vehicles_total");
-        let sql = format!(
-            "SELECT count(*) FROM vehicles WHERE last_updated > now() - interval '15 minutes' {};",
-            self.filters.sql(),
-        );
-
-        println!("{}", sql);
-
-        let query: i64 = sqlx::query(sql.as_str())
-            .fetch_one(pool)
-            .await
-            .unwrap()
-            .get(0);
-
-        query
-    }
-
-    // Transport
-    async fn flash(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "flash");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "flash".to_string(),
-        }
-    }
-    async fn sunderer(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "sunderer");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "sunderer".to_string(),
-        }
-    }
-    async fn ant(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "ant");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "ant".to_string(),
-        }
-    }
-    async fn harasser(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "harasser");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "harasser".to_string(),
-        }
-    }
-    async fn javelin(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "javelin");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "javelin".to_string(),
-        }
-    }
-    async fn corsair(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "corsair");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "corsair".to_string(),
-        }
-    }
-
-    // Tanks
-    async fn lightning(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "lightning");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "lightning".to_string(),
-        }
-    }
-    async fn prowler(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "prowler");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "prowler".to_string(),
-        }
-    }
-    async fn vanguard(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "vanguard");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "vanguard".to_string(),
-        }
-    }
-    async fn magrider(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "magrider");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "magrider".to_string(),
-        }
-    }
-    async fn chimera(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "chimera");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "chimera".to_string(),
-        }
-    }
-
-    // Air
-    async fn mosquito(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "mosquito");
-
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "mosquito".to_string(),
-        }
-    }
-    async fn liberator(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "liberator");
+/// A kind of vehicle. Transports can be ground (Sunderer) or air (Galaxy, Valkyrie).
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum VehicleCategory {
+    /// Vanguard, Prowler, Magrider, Chimera
+    Mbt,
+    /// Lightning, Harasser, Flash, Javelin
+    Light,
+    /// Sunderer, Galaxy, Valkyrie
+    Transport,
+    /// ANT
+    Utility,
+    /// Mosquito, Reaver, Scythe
+    Fighter,
+    /// Liberator, Dervish
+    Bomber,
+    /// Corsair
+    Naval,
+}
 
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "liberator".to_string(),
+impl VehicleCategory {
+    /// The value stored in the `category` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VehicleCategory::Mbt => "mbt",
+            VehicleCategory::Light => "light",
+            VehicleCategory::Transport => "transport",
+            VehicleCategory::Utility => "utility",
+            VehicleCategory::Fighter => "fighter",
+            VehicleCategory::Bomber => "bomber",
+            VehicleCategory::Naval => "naval",
         }
     }
-    async fn galaxy(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "galaxy");
 
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "galaxy".to_string(),
+    fn from_column(category: &str) -> Option<Self> {
+        match category {
+            "mbt" => Some(VehicleCategory::Mbt),
+            "light" => Some(VehicleCategory::Light),
+            "transport" => Some(VehicleCategory::Transport),
+            "utility" => Some(VehicleCategory::Utility),
+            "fighter" => Some(VehicleCategory::Fighter),
+            "bomber" => Some(VehicleCategory::Bomber),
+            "naval" => Some(VehicleCategory::Naval),
+            _ => None,
         }
     }
-    async fn valkyrie(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "valkyrie");
+}
 
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "valkyrie".to_string(),
-        }
-    }
-    async fn reaver(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "reaver");
+/// How many vehicles of a category are active.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct VehicleCategoryTotal {
+    pub category: VehicleCategory,
+    pub total: i64,
+}
 
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "reaver".to_string(),
-        }
-    }
-    async fn scythe(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "scythe");
+/// Super-struct for all vehicles.
+pub struct Vehicles {
+    filters: Filters,
+    /// Minutes since a vehicle was last seen for it to count as active.
+    window: i32,
+}
 
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "scythe".to_string(),
+impl Vehicles {
+    pub fn new(filters: Option<Filters>, window: i32) -> Self {
+        Self {
+            filters: filters.unwrap_or_default(),
+            window,
         }
     }
-    async fn dervish(&self) -> Vehicle {
-        telemetry::graphql_query("Vehicle", "dervish");
+}
 
-        Vehicle {
-            filters: self.filters.clone(),
-            vehicle_name: "dervish".to_string(),
+macro_rules! vehicles_object {
+    ($($vehicle:ident)*) => {
+        #[Object]
+        impl Vehicles {
+            async fn total<'ctx>(&self, ctx: &Context<'ctx>) -> Result<i64> {
+                telemetry::graphql_query("Vehicles", "total");
+
+                counts::count(ctx, Source::Vehicles, self.window, &self.filters, |_| true).await
+            }
+
+            /// True when Postgres can't be reached and these counts are the last ones read successfully.
+            async fn stale<'ctx>(&self, ctx: &Context<'ctx>) -> Result<bool> {
+                telemetry::graphql_query("Vehicles", "stale");
+                counts::stale(ctx, Source::Vehicles, self.window).await
+            }
+
+            /// All aircraft
+            async fn air(&self) -> Vehicle {
+                telemetry::graphql_query("Vehicles", "air");
+
+                Vehicle {
+                    filters: self.filters.clone(),
+                    vehicles: VehicleGroup::Domain("air"),
+                    window: self.window,
+                }
+            }
+            /// All ground vehicles
+            async fn ground(&self) -> Vehicle {
+                telemetry::graphql_query("Vehicles", "ground");
+
+                Vehicle {
+                    filters: self.filters.clone(),
+                    vehicles: VehicleGroup::Domain("ground"),
+                    window: self.window,
+                }
+            }
+            /// All boats
+            async fn sea(&self) -> Vehicle {
+                telemetry::graphql_query("Vehicles", "sea");
+
+                Vehicle {
+                    filters: self.filters.clone(),
+                    vehicles: VehicleGroup::Domain("sea"),
+                    window: self.window,
+                }
+            }
+
+            /// All vehicles of one category
+            async fn category(&self, category: VehicleCategory) -> Vehicle {
+                telemetry::graphql_query("Vehicles", "category");
+
+                Vehicle {
+                    filters: self.filters.clone(),
+                    vehicles: VehicleGroup::Category(category),
+                    window: self.window,
+                }
+            }
+
+            /// Totals for every category, in one go. Categories with no active vehicles are left out.
+            async fn categories<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<VehicleCategoryTotal>> {
+                telemetry::graphql_query("Vehicles", "categories");
+
+                let mut totals: Vec<VehicleCategoryTotal> = Vec::new();
+                for group in counts::counts(ctx, Source::Vehicles, self.window).await?.groups.iter() {
+                    let category = match group.category.as_deref().and_then(VehicleCategory::from_column) {
+                        Some(category) if group.matches(&self.filters) => category,
+                        _ => continue,
+                    };
+                    match totals.iter_mut().find(|total| total.category == category) {
+                        Some(total) => total.total += group.count,
+                        None => totals.push(VehicleCategoryTotal {
+                            category,
+                            total: group.count,
+                        }),
+                    }
+                }
+                totals.sort_by(|a, b| b.total.cmp(&a.total));
+
+                Ok(totals)
+            }
+
+            /// Active vehicles of each kind over time, from per-minute snapshots. `filter` defaults to this query's filter,
+            /// `from` and `to` default to the last day, and `bucket` is in seconds (at least 60).
+            async fn history<'ctx>(
+                &self,
+                ctx: &Context<'ctx>,
+                filter: Option<Filters>,
+                from: Option<DateTime<Utc>>,
+                to: Option<DateTime<Utc>>,
+                #[graphql(default = 300)] bucket: u64,
+            ) -> Result<Vec<HistorySeries>> {
+                telemetry::graphql_query("Vehicles", "history");
+                let filter = filter.unwrap_or_else(|| self.filters.clone());
+                filter.validate()?;
+                let pool = ctx.data::<Pool<Postgres>>()?;
+                let timescale = ctx.data::<Timescale>()?;
+
+                history::series(
+                    pool,
+                    timescale,
+                    "vehicles",
+                    &filter,
+                    &HistoryRange::new(from, to, bucket)?,
+                )
+                .await
+            }
+
+            $(
+                async fn $vehicle(&self) -> Vehicle {
+                    telemetry::graphql_query("Vehicle", stringify!($vehicle));
+
+                    Vehicle {
+                        filters: self.filters.clone(),
+                        vehicles: VehicleGroup::Name(stringify!($vehicle)),
+                        window: self.window,
+                    }
+                }
+            )*
         }
-    }
+    };
 }
 
+for_each_vehicle!(vehicles_object);
+
 #[derive(Default)]
 pub struct VehicleQuery;
 
 #[Object]
 impl VehicleQuery {
-    pub async fn vehicles(&self, filter: Option<Filters>) -> Vehicles {
-        Vehicles::new(filter)
+    /// `window` is how many minutes back a vehicle must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    pub async fn vehicles(
+        &self,
+        filter: Option<Filters>,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Result<Vehicles> {
+        if let Some(filter) = &filter {
+            filter.validate()?;
+        }
+        Ok(Vehicles::new(filter, window))
     }
 }
@@ -1,24 +1,32 @@
 use crate::{
     classes::Classes,
+    outfits::{top_outfits, OutfitPopulation},
     population::Population,
-    utils::{id_or_name_to_id, id_or_name_to_name, Filters, IdOrNameBy, ID_TO_WORLD, WORLD_IDS},
+    telemetry,
+    utils::{
+        check_window, default_window, resolve_id, Filters, IdOrNameBy, Platform, ID_TO_WORLD,
+        WORLD_IDS, WORLD_NAMES,
+    },
     vehicles::Vehicles,
     zone::Zones,
-    telemetry,
 };
-use async_graphql::Object;
+use async_graphql::{Context, Object, Result};
+use catalog::for_each_world;
 
 pub struct World {
+    id: i32,
     filter: Filters,
 }
 
 impl World {
-    pub fn new(filter: IdOrNameBy) -> Self {
+    pub fn new(id: i32) -> Self {
         Self {
+            id,
             filter: Filters {
-                world: Some(filter),
+                world: Some(IdOrNameBy::Id(id)),
                 faction: None,
                 zone: None,
+                platform: None,
             },
         }
     }
@@ -29,62 +37,85 @@ impl World {
 /// This can be fetched at the top level with `world(id: "1")` or `worldByName(name: "Connery")`.
 /// ...or get all of them with `allWorlds`.
 ///
-/// If World.id is not valid or known to the API, World.name will return "Unknown".
+/// Unknown IDs and names are an error, with close names suggested when there are any.
 #[Object]
 impl World {
     /// The ID of the world.
     async fn id(&self) -> i32 {
         telemetry::graphql_query("World", "id");
 
-        id_or_name_to_id(&WORLD_IDS, self.filter.world.as_ref().unwrap()).unwrap()
+        self.id
     }
 
     /// The name of the world, in official game capitalization.
     async fn name(&self) -> String {
         telemetry::graphql_query("World", "name");
 
-        let name = id_or_name_to_name(&ID_TO_WORLD, self.filter.world.as_ref().unwrap()).unwrap();
+        WORLD_NAMES.get(&self.id).unwrap_or(&"Unknown").to_string()
+    }
 
-        // Special case for SolTech, lol.
-        if name == "soltech" {
-            return "SolTech".to_string();
-        }
+    /// The platform this world is played on.
+    async fn platform(&self) -> Platform {
+        telemetry::graphql_query("World", "platform");
 
-        // Capitalize the first letter
-        name[0..1].to_uppercase() + &name[1..]
+        Platform::of_world(self.id)
     }
 
-    /// Population filtered to this world.
-    async fn population(&self) -> Population {
+    /// Population filtered to this world. `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn population(
+        &self,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Population {
         telemetry::graphql_query("World", "population");
 
-        Population::new(Some(Filters {
-            world: self.filter.world.clone(),
-            faction: None,
-            zone: None,
-        }))
-    }
-
-    /// Vehicles filtered to this world.
-    async fn vehicles(&self) -> Vehicles {
+        Population::new(
+            Some(Filters {
+                world: self.filter.world.clone(),
+                faction: None,
+                zone: None,
+                platform: None,
+            }),
+            window,
+        )
+    }
+
+    /// Vehicles filtered to this world. `window` is how many minutes back a vehicle must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn vehicles(
+        &self,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Vehicles {
         telemetry::graphql_query("World", "vehicles");
 
-        Vehicles::new(Some(Filters {
-            world: self.filter.world.clone(),
-            faction: None,
-            zone: None,
-        }))
-    }
-
-    /// Classes filtered to this world.
-    async fn classes(&self) -> Classes {
+        Vehicles::new(
+            Some(Filters {
+                world: self.filter.world.clone(),
+                faction: None,
+                zone: None,
+                platform: None,
+            }),
+            window,
+        )
+    }
+
+    /// Classes filtered to this world. `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn classes(
+        &self,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Classes {
         telemetry::graphql_query("World", "classes");
 
-        Classes::new(Some(Filters {
-            world: self.filter.world.clone(),
-            faction: None,
-            zone: None,
-        }))
+        Classes::new(
+            Some(Filters {
+                world: self.filter.world.clone(),
+                faction: None,
+                zone: None,
+                platform: None,
+            }),
+            window,
+        )
     }
 
     /// Get a specific zone/continent on this world.
@@ -93,72 +124,51 @@ impl World {
 
         Zones::new(Some(self.filter.clone()))
     }
-}
 
-#[derive(Default)]
-pub struct WorldQuery;
-
-#[Object]
-impl WorldQuery {
-    /// A world by ID or name.
-    pub async fn world(&self, by: IdOrNameBy) -> World {
-        World::new(by)
-    }
+    /// Top outfits on this world by active members. `limit` is from 1 to 100, and `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn outfits<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 100))] limit: i64,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Result<Vec<OutfitPopulation>> {
+        telemetry::graphql_query("World", "outfits");
 
-    /// All worlds. This is a convenience method for getting all worlds in one query.
-    /// If you want all of them as aggregate instead of as individual units, use `population`, `vehicles`, `classes` directly instead.
-    pub async fn all_worlds(&self) -> Vec<World> {
-        ID_TO_WORLD
-            .iter()
-            .map(|(id, _)| World::new(IdOrNameBy::Id(*id)))
-            .collect()
-    }
-
-    /// The Connery world in US West on PC
-    /// Shorthand for `world(by: { id: 1 }})`
-    pub async fn connery(&self) -> World {
-        World::new(IdOrNameBy::Id(1))
-    }
-
-    /// The Miller world in EU on PC
-    /// Shorthand for `world(by: { id: 10 }})`
-    pub async fn miller(&self) -> World {
-        World::new(IdOrNameBy::Id(10))
-    }
-
-    /// The Cobalt world in EU on PC
-    /// Shorthand for `world(by: { id: 13 }})`
-    pub async fn cobalt(&self) -> World {
-        World::new(IdOrNameBy::Id(13))
-    }
-
-    /// The Emerald world in US East on PC
-    /// Shorthand for `world(by: { id: 17 }})`
-    pub async fn emerald(&self) -> World {
-        World::new(IdOrNameBy::Id(17))
-    }
-
-    /// The Jaeger world in US East on PC
-    /// Shorthand for `world(by: { id: 19 }})`
-    pub async fn jaeger(&self) -> World {
-        World::new(IdOrNameBy::Id(19))
-    }
-
-    /// The SolTech world in Japan on PC
-    /// Shorthand for `world(by: { id: 40 }})`
-    pub async fn soltech(&self) -> World {
-        World::new(IdOrNameBy::Id(40))
+        top_outfits(ctx, &self.filter, window, limit).await
     }
+}
 
-    /// The Genudine world in US East on PS4
-    /// Shorthand for `world(by: { id: 1000 }})`
-    pub async fn genudine(&self) -> World {
-        World::new(IdOrNameBy::Id(1000))
-    }
+#[derive(Default)]
+pub struct WorldQuery;
 
-    /// The Ceres world in EU on PS4
-    /// Shorthand for `world(by: { id: 2000 }})`
-    pub async fn ceres(&self) -> World {
-        World::new(IdOrNameBy::Id(2000))
-    }
+macro_rules! world_query {
+    ($($field:ident: $id:literal, $name:literal, $platform:ident, $environment:ident, $description:tt;)*) => {
+        #[Object]
+        impl WorldQuery {
+            /// A world by ID or name. Unknown ones are an error, with close names as suggestions.
+            pub async fn world(&self, by: IdOrNameBy) -> Result<World> {
+                Ok(World::new(resolve_id("world", &WORLD_IDS, &by)?))
+            }
+
+            /// All worlds. This is a convenience method for getting all worlds in one query.
+            /// If you want all of them as aggregate instead of as individual units, use `population`, `vehicles`, `classes` directly instead.
+            pub async fn all_worlds(&self) -> Vec<World> {
+                ID_TO_WORLD
+                    .keys()
+                    .map(|id| World::new(*id))
+                    .collect()
+            }
+
+            $(
+                #[doc = $description]
+                /// Shorthand for `world(by: { id: ... })` with this world's ID
+                pub async fn $field(&self) -> World {
+                    World::new($id)
+                }
+            )*
+        }
+    };
 }
+
+for_each_world!(world_query);
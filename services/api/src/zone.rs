@@ -1,21 +1,33 @@
 use crate::{
     classes::Classes,
+    errors::{self, ErrorCode},
+    outfits::{top_outfits, OutfitPopulation},
     population::Population,
-    utils::{id_or_name_to_id, id_or_name_to_name, Filters, IdOrNameBy, ID_TO_ZONE, ZONE_IDS},
-    vehicles::Vehicles,
     telemetry,
+    utils::{
+        check_window, default_window, resolve_id, Filters, IdOrNameBy, ID_TO_ZONE, ZONE_IDS,
+        ZONE_NAMES,
+    },
+    vehicles::Vehicles,
 };
-use async_graphql::Object;
+use async_graphql::{Context, Object, Result};
+use catalog::for_each_zone;
 
 /// An individual zone/continent.
 pub struct Zone {
+    id: i32,
     filters: Filters,
 }
 
 impl Zone {
-    pub fn new(filters: Option<Filters>) -> Self {
+    /// `filters.zone` is replaced with `id`.
+    pub fn new(id: i32, filters: Filters) -> Self {
         Self {
-            filters: filters.unwrap_or_default(),
+            id,
+            filters: Filters {
+                zone: Some(IdOrNameBy::Id(id)),
+                ..filters
+            },
         }
     }
 }
@@ -26,35 +38,60 @@ impl Zone {
     async fn id(&self) -> i32 {
         telemetry::graphql_query("Zone", "id");
 
-        id_or_name_to_id(&ZONE_IDS, self.filters.zone.as_ref().unwrap()).unwrap()
+        self.id
     }
 
     /// The name of the continent, in official game capitalization.
     async fn name(&self) -> String {
         telemetry::graphql_query("Zone", "name");
 
-        let name = id_or_name_to_name(&ID_TO_ZONE, self.filters.zone.as_ref().unwrap()).unwrap();
-
-        // Capitalize the first letter
-        name[0..1].to_uppercase() + &name[1..]
+        ZONE_NAMES.get(&self.id).unwrap_or(&"Unknown").to_string()
     }
 
-    async fn population(&self) -> Population {
+    /// `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn population(
+        &self,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Population {
         telemetry::graphql_query("Zone", "population");
 
-        Population::new(Some(self.filters.clone()))
+        Population::new(Some(self.filters.clone()), window)
     }
 
-    async fn vehicles(&self) -> Vehicles {
+    /// `window` is how many minutes back a vehicle must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn vehicles(
+        &self,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Vehicles {
         telemetry::graphql_query("Zone", "vehicles");
 
-        Vehicles::new(Some(self.filters.clone()))
+        Vehicles::new(Some(self.filters.clone()), window)
     }
 
-    async fn classes(&self) -> Classes {
+    /// `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn classes(
+        &self,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Classes {
         telemetry::graphql_query("Zone", "classes");
 
-        Classes::new(Some(self.filters.clone()))
+        Classes::new(Some(self.filters.clone()), window)
+    }
+
+    /// Top outfits on this zone/continent by active members. `limit` is from 1 to 100, and `window` is how many minutes back a player must have been seen to count, from 1 up to however many minutes are kept, 15 by default.
+    async fn outfits<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 100))] limit: i64,
+        #[graphql(default_with = "default_window()", validator(custom = "check_window"))]
+        window: i32,
+    ) -> Result<Vec<OutfitPopulation>> {
+        telemetry::graphql_query("Zone", "outfits");
+
+        top_outfits(ctx, &self.filters, window, limit).await
     }
 }
 
@@ -69,75 +106,61 @@ impl Zones {
             filters: filters.unwrap_or_default(),
         }
     }
-}
-
-#[Object]
-impl Zones {
-    /// Every zone/continent individually.
-    async fn all(&self) -> Vec<Zone> {
-        ID_TO_ZONE
-            .iter()
-            .map(|(id, _)| {
-                Zone::new(Some(Filters {
-                    world: self.filters.world.clone(),
-                    faction: self.filters.faction.clone(),
-                    zone: Some(IdOrNameBy::Id(*id)),
-                }))
-            })
-            .collect()
-    }
 
-    async fn indar(&self) -> Zone {
-        Zone::new(Some(Filters {
-            world: self.filters.world.clone(),
-            faction: self.filters.faction.clone(),
-            zone: Some(IdOrNameBy::Id(2)),
-        }))
-    }
-
-    async fn hossin(&self) -> Zone {
-        Zone::new(Some(Filters {
-            world: self.filters.world.clone(),
-            faction: self.filters.faction.clone(),
-            zone: Some(IdOrNameBy::Id(4)),
-        }))
-    }
-
-    async fn amerish(&self) -> Zone {
-        Zone::new(Some(Filters {
-            world: self.filters.world.clone(),
-            faction: self.filters.faction.clone(),
-            zone: Some(IdOrNameBy::Id(6)),
-        }))
-    }
-
-    async fn esamir(&self) -> Zone {
-        Zone::new(Some(Filters {
-            world: self.filters.world.clone(),
-            faction: self.filters.faction.clone(),
-            zone: Some(IdOrNameBy::Id(8)),
-        }))
+    fn zone(&self, zone_id: i32) -> Zone {
+        Zone::new(zone_id, self.filters.clone())
     }
+}
 
-    async fn oshur(&self) -> Zone {
-        Zone::new(Some(Filters {
-            world: self.filters.world.clone(),
-            faction: self.filters.faction.clone(),
-            zone: Some(IdOrNameBy::Id(344)),
-        }))
-    }
+macro_rules! zones_object {
+    ($($field:ident: $id:literal, $name:literal;)*) => {
+        #[Object]
+        impl Zones {
+            /// Every zone/continent individually.
+            async fn all(&self) -> Vec<Zone> {
+                ID_TO_ZONE
+                    .keys()
+                    .map(|id| self.zone(*id))
+                    .collect()
+            }
+
+            $(
+                async fn $field(&self) -> Zone {
+                    self.zone($id)
+                }
+            )*
+        }
+    };
 }
 
+for_each_zone!(zones_object);
+
 #[derive(Default)]
 pub struct ZoneQuery;
 
 #[Object]
 impl ZoneQuery {
-    pub async fn zone(&self, filter: Option<Filters>) -> Zone {
-        Zone::new(filter)
+    /// A zone/continent, picked by `filter.zone`, which is required.
+    pub async fn zone(&self, filter: Option<Filters>) -> Result<Zone> {
+        let filter = filter.unwrap_or_default();
+        filter.validate()?;
+        let id = match &filter.zone {
+            Some(zone) => resolve_id("zone", &ZONE_IDS, zone)?,
+            None => {
+                return Err(errors::error(
+                    ErrorCode::MissingFilter,
+                    "zone needs filter.zone to pick a zone",
+                ))
+            }
+        };
+
+        Ok(Zone::new(id, filter))
     }
 
-    pub async fn zones(&self, filter: Option<Filters>) -> Zones {
-        Zones::new(filter)
+    pub async fn zones(&self, filter: Option<Filters>) -> Result<Zones> {
+        if let Some(filter) = &filter {
+            filter.validate()?;
+        }
+        Ok(Zones::new(filter))
     }
 }
@@ -0,0 +1,275 @@
+use crate::{
+    migrations::{pending_migrations, TableKind, EXPECTED_TABLES},
+    retention, PG,
+};
+use sqlx::{query, Row};
+
+/// Tables that should be getting new rows while ingest is running, and how old their newest
+/// row can be before it's a problem.
+static FRESHNESS: &[(&str, &str, &str)] = &[
+    ("players", "last_updated", "5 minutes"),
+    ("vehicles", "last_updated", "15 minutes"),
+    ("analytics", "time", "5 minutes"),
+    ("population_history", "time", "5 minutes"),
+];
+
+#[derive(Default)]
+struct Report {
+    failures: u32,
+}
+
+impl Report {
+    fn check(&mut self, ok: bool, name: &str, detail: impl AsRef<str>) {
+        if !ok {
+            self.failures += 1;
+        }
+        let line = format!(
+            "  {}  {:<42} {}",
+            if ok { "PASS" } else { "FAIL" },
+            name,
+            detail.as_ref()
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+async fn timescale_version() -> Option<String> {
+    query("SELECT extversion FROM pg_extension WHERE extname = 'timescaledb';")
+        .fetch_optional(PG.get().await)
+        .await
+        .unwrap()
+        .map(|row| row.get(0))
+}
+
+async fn check_migrations(report: &mut Report) {
+    match pending_migrations().await {
+        None => report.check(
+            false,
+            "migrations",
+            "schema_migrations is missing, run `tasks migrate`",
+        ),
+        Some(pending) if pending.is_empty() => report.check(true, "migrations", "all applied"),
+        Some(pending) => report.check(
+            false,
+            "migrations",
+            format!(
+                "pending: {}",
+                pending
+                    .iter()
+                    .map(|(version, name)| format!("{} {}", version, name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ),
+    }
+}
+
+async fn check_tables(report: &mut Report, timescale: bool) {
+    let pool = PG.get().await;
+
+    for table in EXPECTED_TABLES {
+        let columns: Vec<(String, String)> = query(
+            "SELECT column_name::text, udt_name::text FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1;",
+        )
+        .bind(table.name)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+        if columns.is_empty() {
+            report.check(false, &format!("table {}", table.name), "missing");
+            continue;
+        }
+
+        let problems: Vec<String> = table
+            .columns
+            .iter()
+            .filter_map(
+                |(name, udt)| match columns.iter().find(|(column, _)| column == name) {
+                    None => Some(format!("no {}", name)),
+                    Some((_, actual)) if actual != udt => {
+                        Some(format!("{} is {}, expected {}", name, actual, udt))
+                    }
+                    Some(_) => None,
+                },
+            )
+            .collect();
+        report.check(
+            problems.is_empty(),
+            &format!("table {}", table.name),
+            if problems.is_empty() {
+                format!("{} columns", table.columns.len())
+            } else {
+                problems.join(", ")
+            },
+        );
+
+        for index in table.indexes {
+            let exists: bool = query(
+                "SELECT EXISTS (SELECT 1 FROM pg_indexes
+                WHERE schemaname = current_schema() AND tablename = $1 AND indexname = $2);",
+            )
+            .bind(table.name)
+            .bind(index)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .get(0);
+            report.check(
+                exists,
+                &format!("index {}", index),
+                if exists { "" } else { "missing" },
+            );
+        }
+
+        let (check, sql) = match table.kind {
+            TableKind::Table => continue,
+            TableKind::Hypertable if !timescale => (
+                "partitioned",
+                "SELECT EXISTS (SELECT 1 FROM pg_partitioned_table WHERE partrelid = to_regclass($1));",
+            ),
+            TableKind::ContinuousAggregate if !timescale => continue,
+            TableKind::Hypertable => (
+                "hypertable",
+                "SELECT EXISTS (SELECT 1 FROM timescaledb_information.hypertables WHERE hypertable_name = $1);",
+            ),
+            TableKind::ContinuousAggregate => (
+                "continuous aggregate",
+                "SELECT EXISTS (SELECT 1 FROM timescaledb_information.continuous_aggregates WHERE view_name = $1);",
+            ),
+        };
+        let exists: bool = query(sql)
+            .bind(table.name)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .get(0);
+        report.check(
+            exists,
+            &format!("{} {}", check, table.name),
+            if exists { "" } else { "not set up" },
+        );
+    }
+}
+
+async fn check_policies(report: &mut Report) {
+    let pool = PG.get().await;
+
+    for (table, interval) in retention::policies() {
+        let name = format!("retention policy {}", table);
+        match retention::current_policy(table).await {
+            None => report.check(false, &name, "missing, run `tasks retention`"),
+            Some(current) => {
+                let ok = retention::same_interval(&current, &interval).await;
+                report.check(
+                    ok,
+                    &name,
+                    if ok {
+                        current
+                    } else {
+                        format!("{}, expected {}, run `tasks retention`", current, interval)
+                    },
+                );
+            }
+        }
+    }
+
+    for (proc_name, table) in [
+        ("policy_compression", "analytics"),
+        ("policy_refresh_continuous_aggregate", "analytics_1m"),
+        ("policy_refresh_continuous_aggregate", "analytics_1h"),
+    ] {
+        let exists: bool = query(
+            "SELECT EXISTS (SELECT 1 FROM timescaledb_information.jobs
+            WHERE proc_name = $1 AND (
+                hypertable_name = $2
+                OR hypertable_name IN (SELECT materialization_hypertable_name
+                    FROM timescaledb_information.continuous_aggregates WHERE view_name = $2)
+            ));",
+        )
+        .bind(proc_name)
+        .bind(table)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .get(0);
+        report.check(
+            exists,
+            &format!(
+                "{} {}",
+                proc_name.trim_start_matches("policy_").replace('_', " "),
+                table
+            ),
+            if exists { "" } else { "missing" },
+        );
+    }
+}
+
+async fn check_freshness(report: &mut Report) {
+    let pool = PG.get().await;
+
+    for (table, column, max_age) in FRESHNESS {
+        let row = query(
+            format!(
+                "SELECT (now() - max({0}))::text, max({0}) > now() - $1::interval FROM {1};",
+                column, table
+            )
+            .as_str(),
+        )
+        .bind(max_age)
+        .fetch_one(pool)
+        .await;
+
+        let name = format!("freshness {}", table);
+        match row {
+            Ok(row) => match row.get::<Option<String>, _>(0) {
+                Some(age) => report.check(
+                    row.get::<Option<bool>, _>(1).unwrap_or(false),
+                    &name,
+                    format!("newest row is {} old, limit {}", age, max_age),
+                ),
+                None => report.check(false, &name, "no rows"),
+            },
+            Err(e) => report.check(false, &name, e.to_string()),
+        }
+    }
+}
+
+/// Checks the database is set up the way the services expect, and exits 1 if anything's wrong.
+pub async fn cmd_doctor() {
+    let mut report = Report::default();
+
+    println!("Database:");
+    let timescale = timescale_version().await;
+    // Optional, so its absence is only noted
+    report.check(
+        true,
+        "timescaledb extension",
+        timescale
+            .as_deref()
+            .unwrap_or("not installed, using plain Postgres"),
+    );
+
+    println!("Schema:");
+    check_migrations(&mut report).await;
+    check_tables(&mut report, timescale.is_some()).await;
+
+    if timescale.is_some() {
+        println!("Policies:");
+        check_policies(&mut report).await;
+    }
+
+    println!("Freshness:");
+    check_freshness(&mut report).await;
+
+    if report.failures > 0 {
+        println!("{} problem(s) found", report.failures);
+        std::process::exit(1);
+    }
+
+    println!("Everything looks good!");
+}
@@ -0,0 +1,507 @@
+use crate::PG;
+use arrow_array::{
+    builder::{
+        BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder,
+        TimestampMicrosecondBuilder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use catalog::{for_each_world, for_each_zone};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::TryStreamExt;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use sqlx::{postgres::PgRow, query, Column, Executor, Row, TypeInfo};
+use std::{io::Write, sync::Arc};
+
+/// Tables that can be exported, and the column their time range applies to.
+static TABLES: &[(&str, &str)] = &[
+    ("analytics", "time"),
+    ("analytics_1m", "bucket"),
+    ("analytics_1h", "bucket"),
+    ("vehicle_destroys", "time"),
+    ("sessions", "first_seen"),
+    ("population_history", "time"),
+];
+
+/// Rows per Parquet row group, which is also how many rows are held in memory at once.
+const PARQUET_BATCH_SIZE: usize = 8192;
+
+macro_rules! id_lookup {
+    ($($field:ident: $id:literal, $($rest:tt),*;)*) => {
+        |name: &str| match name {
+            $(stringify!($field) => Some($id),)*
+            _ => None,
+        }
+    };
+}
+
+fn world_id(name: &str) -> Option<i32> {
+    let lookup = for_each_world!(id_lookup);
+    lookup(name)
+}
+
+fn zone_id(name: &str) -> Option<i32> {
+    let lookup = for_each_zone!(id_lookup);
+    lookup(name)
+}
+
+fn faction_id(name: &str) -> Option<i32> {
+    match name {
+        "vs" => Some(1),
+        "nc" => Some(2),
+        "tr" => Some(3),
+        "ns" => Some(4),
+        _ => None,
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: tasks export <table> [options]");
+    eprintln!(
+        "Tables: {}",
+        TABLES
+            .iter()
+            .map(|(table, _)| *table)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    eprintln!("Options:");
+    eprintln!("  --format csv|ndjson|parquet  Output format, default csv");
+    eprintln!("  --output <file>              Write to a file instead of stdout");
+    eprintln!("  --from <time>, --to <time>   RFC 3339 time range, default the last day");
+    eprintln!("  --world <id or name>         Like 1 or connery");
+    eprintln!("  --zone <id or name>          Like 2 or indar");
+    eprintln!("  --faction <id or name>       Like 1 or vs");
+    eprintln!("  --platform pc|ps4");
+    eprintln!("  --event <name>               Event name, like Death");
+    std::process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("[export] ERR => {}", message);
+    std::process::exit(2);
+}
+
+enum Format {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+/// A column filter, like the API's `Filters`.
+enum Bound {
+    Int(i32),
+    Text(String),
+}
+
+struct ExportArgs {
+    table: &'static str,
+    time_column: &'static str,
+    format: Format,
+    output: Option<String>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    filters: Vec<(&'static str, Bound)>,
+}
+
+/// Accepts an ID, or a name from `lookup` (case-insensitive).
+fn id_or_name(flag: &str, value: &str, lookup: fn(&str) -> Option<i32>) -> Bound {
+    value
+        .parse()
+        .ok()
+        .or_else(|| lookup(value.to_lowercase().as_str()))
+        .map(Bound::Int)
+        .unwrap_or_else(|| fail(format!("{} {:?} isn't an ID or a known name", flag, value)))
+}
+
+fn parse_time(flag: &str, value: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|e| fail(format!("{} {:?}: {}", flag, value, e)))
+}
+
+fn parse_args(args: &[String]) -> ExportArgs {
+    let (table, time_column) = match args.first() {
+        Some(table) => *TABLES
+            .iter()
+            .find(|(name, _)| name == table)
+            .unwrap_or_else(|| fail(format!("can't export {:?}", table))),
+        None => usage(),
+    };
+
+    let mut format = Format::Csv;
+    let mut output = None;
+    let mut from = None;
+    let mut to = None;
+    let mut filters = Vec::new();
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--format" => {
+                format = match value.as_str() {
+                    "csv" => Format::Csv,
+                    "ndjson" => Format::Ndjson,
+                    "parquet" => Format::Parquet,
+                    _ => usage(),
+                }
+            }
+            "--output" => output = Some(value.clone()),
+            "--from" => from = Some(parse_time(flag, value)),
+            "--to" => to = Some(parse_time(flag, value)),
+            "--world" => filters.push(("world_id", id_or_name(flag, value, world_id))),
+            "--zone" => filters.push(("zone_id", id_or_name(flag, value, zone_id))),
+            "--faction" => filters.push(("faction_id", id_or_name(flag, value, faction_id))),
+            "--platform" => filters.push(("platform", Bound::Text(value.to_lowercase()))),
+            "--event" => filters.push(("event_name", Bound::Text(value.clone()))),
+            _ => usage(),
+        }
+    }
+
+    let to = to.unwrap_or_else(Utc::now);
+    let from = from.unwrap_or(to - Duration::days(1));
+
+    ExportArgs {
+        table,
+        time_column,
+        format,
+        output,
+        from,
+        to,
+        filters,
+    }
+}
+
+/// How a column is read from Postgres and written out.
+#[derive(Clone, Copy)]
+enum Kind {
+    Int4,
+    Int8,
+    Float8,
+    Bool,
+    Text,
+    Time,
+}
+
+impl Kind {
+    /// Types without a direct mapping are exported as text.
+    fn of(type_name: &str) -> Self {
+        match type_name {
+            "INT4" => Kind::Int4,
+            "INT8" => Kind::Int8,
+            "FLOAT8" => Kind::Float8,
+            "BOOL" => Kind::Bool,
+            "TIMESTAMPTZ" => Kind::Time,
+            _ => Kind::Text,
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Kind::Int4 => DataType::Int32,
+            Kind::Int8 => DataType::Int64,
+            Kind::Float8 => DataType::Float64,
+            Kind::Bool => DataType::Boolean,
+            Kind::Text => DataType::Utf8,
+            Kind::Time => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        }
+    }
+
+    fn value(&self, row: &PgRow, index: usize) -> Value {
+        let value = match self {
+            Kind::Int4 => row
+                .get::<Option<i32>, _>(index)
+                .map(|v| Value::Int(v as i64)),
+            Kind::Int8 => row.get::<Option<i64>, _>(index).map(Value::Int),
+            Kind::Float8 => row.get::<Option<f64>, _>(index).map(Value::Float),
+            Kind::Bool => row.get::<Option<bool>, _>(index).map(Value::Bool),
+            Kind::Text => row.get::<Option<String>, _>(index).map(Value::Text),
+            Kind::Time => row.get::<Option<DateTime<Utc>>, _>(index).map(Value::Time),
+        };
+        value.unwrap_or(Value::Null)
+    }
+}
+
+enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    Time(DateTime<Utc>),
+}
+
+impl Value {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Int(v) => (*v).into(),
+            Value::Float(v) => (*v).into(),
+            Value::Bool(v) => (*v).into(),
+            Value::Text(v) => v.as_str().into(),
+            Value::Time(v) => v.to_rfc3339().into(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Int(v) => v.to_string(),
+            Value::Float(v) => v.to_string(),
+            Value::Bool(v) => v.to_string(),
+            Value::Text(v) => v.clone(),
+            Value::Time(v) => v.to_rfc3339(),
+        }
+    }
+}
+
+/// One Arrow column being filled in for the next Parquet row group.
+enum ColumnBuilder {
+    Int4(Int32Builder),
+    Int8(Int64Builder),
+    Float8(Float64Builder),
+    Bool(BooleanBuilder),
+    Text(StringBuilder),
+    Time(TimestampMicrosecondBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: Kind) -> Self {
+        match kind {
+            Kind::Int4 => ColumnBuilder::Int4(Int32Builder::new()),
+            Kind::Int8 => ColumnBuilder::Int8(Int64Builder::new()),
+            Kind::Float8 => ColumnBuilder::Float8(Float64Builder::new()),
+            Kind::Bool => ColumnBuilder::Bool(BooleanBuilder::new()),
+            Kind::Text => ColumnBuilder::Text(StringBuilder::new()),
+            Kind::Time => {
+                ColumnBuilder::Time(TimestampMicrosecondBuilder::new().with_timezone("UTC"))
+            }
+        }
+    }
+
+    fn append(&mut self, value: Value) {
+        match (self, value) {
+            (ColumnBuilder::Int4(b), Value::Int(v)) => b.append_value(v as i32),
+            (ColumnBuilder::Int8(b), Value::Int(v)) => b.append_value(v),
+            (ColumnBuilder::Float8(b), Value::Float(v)) => b.append_value(v),
+            (ColumnBuilder::Bool(b), Value::Bool(v)) => b.append_value(v),
+            (ColumnBuilder::Text(b), Value::Text(v)) => b.append_value(v),
+            (ColumnBuilder::Time(b), Value::Time(v)) => b.append_value(v.timestamp_micros()),
+            (ColumnBuilder::Int4(b), _) => b.append_null(),
+            (ColumnBuilder::Int8(b), _) => b.append_null(),
+            (ColumnBuilder::Float8(b), _) => b.append_null(),
+            (ColumnBuilder::Bool(b), _) => b.append_null(),
+            (ColumnBuilder::Text(b), _) => b.append_null(),
+            (ColumnBuilder::Time(b), _) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int4(b) => Arc::new(b.finish()),
+            ColumnBuilder::Int8(b) => Arc::new(b.finish()),
+            ColumnBuilder::Float8(b) => Arc::new(b.finish()),
+            ColumnBuilder::Bool(b) => Arc::new(b.finish()),
+            ColumnBuilder::Text(b) => Arc::new(b.finish()),
+            ColumnBuilder::Time(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Where rows go. Each format writes as it goes, so only Parquet holds (one batch of) rows.
+enum Sink {
+    Csv(csv::Writer<Box<dyn Write + Send>>),
+    Ndjson(Box<dyn Write + Send>),
+    Parquet {
+        writer: ArrowWriter<Box<dyn Write + Send>>,
+        schema: Arc<Schema>,
+        builders: Vec<ColumnBuilder>,
+        rows: usize,
+    },
+}
+
+impl Sink {
+    fn new(format: &Format, out: Box<dyn Write + Send>, columns: &[(String, Kind)]) -> Self {
+        match format {
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer
+                    .write_record(columns.iter().map(|(name, _)| name))
+                    .unwrap();
+                Sink::Csv(writer)
+            }
+            Format::Ndjson => Sink::Ndjson(out),
+            Format::Parquet => {
+                let schema = Arc::new(Schema::new(
+                    columns
+                        .iter()
+                        .map(|(name, kind)| Field::new(name, kind.data_type(), true))
+                        .collect::<Vec<_>>(),
+                ));
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+
+                Sink::Parquet {
+                    writer: ArrowWriter::try_new(out, schema.clone(), Some(properties)).unwrap(),
+                    schema,
+                    builders: columns
+                        .iter()
+                        .map(|(_, kind)| ColumnBuilder::new(*kind))
+                        .collect(),
+                    rows: 0,
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, columns: &[(String, Kind)], row: &PgRow) {
+        let values = columns
+            .iter()
+            .enumerate()
+            .map(|(index, (_, kind))| kind.value(row, index));
+
+        match self {
+            Sink::Csv(writer) => {
+                writer
+                    .write_record(values.map(|value| value.to_csv()))
+                    .unwrap();
+            }
+            Sink::Ndjson(out) => {
+                let object: serde_json::Map<String, serde_json::Value> = columns
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .zip(values.map(|value| value.to_json()))
+                    .collect();
+                serde_json::to_writer(&mut *out, &object).unwrap();
+                out.write_all(b"\n").unwrap();
+            }
+            Sink::Parquet { builders, rows, .. } => {
+                for (builder, value) in builders.iter_mut().zip(values) {
+                    builder.append(value);
+                }
+                *rows += 1;
+                if *rows >= PARQUET_BATCH_SIZE {
+                    self.flush();
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Sink::Parquet {
+            writer,
+            schema,
+            builders,
+            rows,
+        } = self
+        {
+            if *rows == 0 {
+                return;
+            }
+            let arrays = builders
+                .iter_mut()
+                .map(|builder| builder.finish())
+                .collect();
+            writer
+                .write(&RecordBatch::try_new(schema.clone(), arrays).unwrap())
+                .unwrap();
+            *rows = 0;
+        }
+    }
+
+    fn finish(mut self) {
+        self.flush();
+        match self {
+            Sink::Csv(mut writer) => writer.flush().unwrap(),
+            Sink::Ndjson(mut out) => out.flush().unwrap(),
+            Sink::Parquet { writer, .. } => {
+                writer.close().unwrap();
+            }
+        }
+    }
+}
+
+/// Streams a time-series table to CSV, NDJSON, or Parquet. Progress goes to stderr, so stdout
+/// can be piped.
+pub async fn cmd_export(args: &[String]) {
+    let args = parse_args(args);
+    let pool = PG.get().await;
+
+    // Find the table's columns and types before reading any rows, so Parquet knows its schema
+    let described = pool
+        .describe(format!("SELECT * FROM {};", args.table).as_str())
+        .await
+        .unwrap_or_else(|e| fail(format!("can't read {}: {}", args.table, e)));
+    let columns: Vec<(String, Kind)> = described
+        .columns()
+        .iter()
+        .map(|column| {
+            (
+                column.name().to_string(),
+                Kind::of(column.type_info().name()),
+            )
+        })
+        .collect();
+
+    let mut conditions = vec![format!("{0} >= $1 AND {0} < $2", args.time_column)];
+    for (index, (column, _)) in args.filters.iter().enumerate() {
+        if !columns.iter().any(|(name, _)| name == column) {
+            fail(format!(
+                "{} has no {} column to filter on",
+                args.table, column
+            ));
+        }
+        conditions.push(format!("{} = ${}", column, index + 3));
+    }
+
+    let select: Vec<String> = columns
+        .iter()
+        .map(|(name, kind)| match kind {
+            Kind::Text => format!("{}::text", name),
+            _ => name.clone(),
+        })
+        .collect();
+    let sql = format!(
+        "SELECT {} FROM {} WHERE {} ORDER BY {};",
+        select.join(", "),
+        args.table,
+        conditions.join(" AND "),
+        args.time_column
+    );
+
+    let out: Box<dyn Write + Send> = match &args.output {
+        Some(path) => Box::new(std::io::BufWriter::new(
+            std::fs::File::create(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e))),
+        )),
+        None => Box::new(std::io::BufWriter::new(std::io::stdout())),
+    };
+    let mut sink = Sink::new(&args.format, out, &columns);
+
+    eprintln!(
+        "Exporting {} from {} to {}...",
+        args.table,
+        args.from.to_rfc3339(),
+        args.to.to_rfc3339()
+    );
+
+    let mut statement = query(sql.as_str()).bind(args.from).bind(args.to);
+    for (_, bound) in &args.filters {
+        statement = match bound {
+            Bound::Int(value) => statement.bind(*value),
+            Bound::Text(value) => statement.bind(value.as_str()),
+        };
+    }
+
+    let mut rows = statement.fetch(pool);
+    let mut count: u64 = 0;
+    while let Some(row) = rows.try_next().await.unwrap() {
+        sink.write(&columns, &row);
+        count += 1;
+    }
+    sink.finish();
+
+    eprintln!("Exported {} rows of {}", count, args.table);
+}
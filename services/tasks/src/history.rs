@@ -0,0 +1,34 @@
+use crate::{retention, PG};
+use sqlx::query;
+
+/// Copies the current counts from `players` and `vehicles` into `population_history`, one row
+/// per world, zone, faction, platform, and class or vehicle. Returns the rows written.
+///
+/// Counts cover the same window the API's counts default to, the shorter of the two tables'
+/// retention, so history lines up with the live numbers.
+pub async fn snapshot() -> u64 {
+    let pool = PG.get().await;
+    let mut rows = 0;
+
+    for (source, name_column) in [("players", "class_name"), ("vehicles", "vehicle_name")] {
+        let sql = format!(
+            "INSERT INTO population_history (time, source, world_id, zone_id, faction_id, platform, name, count)
+            SELECT date_trunc('minute', now()), $1, world_id, zone_id, faction_id, platform, {0}, count(*)
+            FROM {1}
+            WHERE last_updated > now() - least($2::interval, $3::interval)
+            GROUP BY world_id, zone_id, faction_id, platform, {0};",
+            name_column, source
+        );
+
+        rows += query(sql.as_str())
+            .bind(source)
+            .bind(retention::interval("players"))
+            .bind(retention::interval("vehicles"))
+            .execute(pool)
+            .await
+            .unwrap()
+            .rows_affected();
+    }
+
+    rows
+}
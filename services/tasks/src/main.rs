@@ -1,10 +1,19 @@
 use async_once::AsyncOnce;
 use lazy_static::lazy_static;
-use migrations::cmd_migrate;
-use sqlx::query;
+use migrations::{cmd_migrate, migrate_pending};
+use retention::cmd_prune;
+use scheduler::Job;
+use sqlx::Row;
 use std::env::args;
 
+mod doctor;
+mod export;
+mod history;
 mod migrations;
+mod retention;
+mod scheduler;
+mod seed;
+mod telemetry;
 
 lazy_static! {
     pub static ref PG: AsyncOnce<sqlx::PgPool> = AsyncOnce::new(async {
@@ -14,38 +23,66 @@ lazy_static! {
     });
 }
 
-async fn cmd_prune() {
-    println!("Pruning old data...");
-    let pool = PG.get().await;
-
-    let rows = query("DELETE FROM players WHERE last_updated < NOW() - INTERVAL '15 minutes';")
-        .execute(pool)
+/// Whether the database has TimescaleDB. Without it, everything still works on plain Postgres,
+/// but there are no Timescale policies, so `prune` is what keeps tables from growing.
+pub async fn has_timescale() -> bool {
+    sqlx::query("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb');")
+        .fetch_one(PG.get().await)
         .await
         .unwrap()
-        .rows_affected();
-    println!("Deleted {} rows of old player data", rows);
+        .get(0)
+}
 
-    let rows = query("DELETE FROM vehicles WHERE last_updated < NOW() - INTERVAL '15 minutes';")
-        .execute(pool)
-        .await
-        .unwrap()
-        .rows_affected();
-    println!("Deleted {} rows of old vehicle data", rows);
+/// Jobs the scheduler knows about. Each can be rescheduled with `JOB_<NAME>_SCHEDULE`.
+static JOBS: &[Job] = &[
+    Job {
+        name: "migrate",
+        default_schedule: "5m",
+        run: || {
+            Box::pin(async {
+                let applied = migrate_pending().await;
+                retention::sync_policies().await;
+                applied
+            })
+        },
+    },
+    Job {
+        name: "prune",
+        default_schedule: "5m",
+        run: || Box::pin(cmd_prune(false)),
+    },
+    Job {
+        name: "snapshot",
+        default_schedule: "0 * * * * *",
+        run: || Box::pin(history::snapshot()),
+    },
+];
 
-    let rows = query("DELETE FROM analytics WHERE time < NOW() - INTERVAL '1 day';")
-        .execute(pool)
-        .await
-        .unwrap()
-        .rows_affected();
-    println!("Deleted {} rows of old analytics data", rows);
+fn job(name: &str) -> &'static Job {
+    JOBS.iter().find(|job| job.name == name).unwrap()
 }
 
 fn cmd_help() {
-    println!("Usage: {} [command]", args().nth(0).unwrap());
+    println!("Usage: {} [command]", args().next().unwrap());
     println!("Commands:");
     println!("  help - Show this help message");
-    println!("  prune - Remove stale data from Redis");
-    println!("  migrate - Reset and create database tables");
+    println!(
+        "  prune - Remove data older than each table's retention, add --dry-run to only count it"
+    );
+    println!("  scheduler - Run every job on its schedule, with /healthz, /metrics, and POST /jobs/:name/run");
+    println!("  auto-prune - Run only the prune job on its schedule");
+    println!("  maintenance - Apply pending migrations, update retention policies, and prune once");
+    println!("  auto-maintenance - Same as scheduler");
+    println!("  doctor - Check the database's extensions, tables, policies, and data freshness");
+    println!("  export <table> - Write a time-series table to CSV, NDJSON, or Parquet, see `export` for options");
+    println!("  seed - Fill players, vehicles, and analytics with synthetic data, see `seed --help` for options");
+    println!("  snapshot - Record current population into population_history once");
+    println!(
+        "  retention - Show retention per table and update Timescale retention policies to match"
+    );
+    println!("  migrate - Apply pending database migrations");
+    println!("  migrate status - Show applied and pending migrations");
+    println!("  migrate down - Roll back the most recent migration");
 }
 
 #[tokio::main]
@@ -54,34 +91,32 @@ async fn main() {
 
     match command.as_str() {
         "help" => cmd_help(),
-        "prune" => cmd_prune().await,
-        "auto-prune" => loop {
-            cmd_prune().await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(60 * 5)).await;
-        },
+        "prune" => {
+            cmd_prune(args().any(|arg| arg == "--dry-run")).await;
+        }
+        "scheduler" | "auto-maintenance" => scheduler::run(JOBS.iter().collect()).await,
+        "auto-prune" => scheduler::run(vec![job("prune")]).await,
         "maintenance" => {
             println!("Running maintenance tasks...");
-            println!("Checking if DB is migrated...");
-            if !migrations::is_migrated().await {
-                println!("DB is not migrated, running migrations...");
-                cmd_migrate().await;
-            }
+            println!("Applying pending migrations...");
+            migrate_pending().await;
+            retention::sync_policies().await;
 
             println!("Running prune...");
-            cmd_prune().await;
+            cmd_prune(false).await;
             println!("Done!");
         }
-        "auto-maintenance" => loop {
-            println!("Running maintenance tasks...");
-            if !migrations::is_migrated().await {
-                println!("DB is not migrated, running migrations...");
-                cmd_migrate().await;
-            }
-
-            cmd_prune().await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(60 * 5)).await;
-        },
-        "migrate" => cmd_migrate().await,
+        "doctor" => doctor::cmd_doctor().await,
+        "export" => export::cmd_export(&args().skip(2).collect::<Vec<_>>()).await,
+        "seed" => seed::cmd_seed(&args().skip(2).collect::<Vec<_>>()).await,
+        "snapshot" => {
+            history::snapshot().await;
+        }
+        "migrate" => cmd_migrate(args().nth(2)).await,
+        "retention" => {
+            retention::print_rules(has_timescale().await);
+            retention::sync_policies().await;
+        }
         "print-env" => {
             std::env::vars().for_each(|(key, value)| println!("{}={}", key, value));
         }
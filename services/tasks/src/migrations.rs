@@ -1,111 +1,761 @@
-use crate::PG;
+use crate::{has_timescale, PG};
 use sqlx::{query, Row};
+use Step::{Plain, Sql};
 
-pub async fn cmd_migrate() {
-    println!("Migrating database...");
+/// One schema change. Applied migrations are recorded in `schema_migrations`, so each runs once.
+///
+/// Steps are run in order inside one transaction. They should be written so they're harmless on
+/// databases that already have the change, since databases from before versioned migrations
+/// start out with nothing recorded.
+struct Migration {
+    version: i64,
+    name: &'static str,
+    up: &'static [Step],
+    /// Undoes `up`. None if the migration can't be rolled back.
+    down: Option<&'static [Step]>,
+}
 
-    tokio::join!(migrate_players(), migrate_vehicles(), migrate_analytics());
+/// One statement of a migration.
+enum Step {
+    /// Runs unless it needs TimescaleDB and that isn't installed.
+    Sql(&'static str),
+    /// Only runs when TimescaleDB isn't installed, in place of what Timescale would do.
+    Plain(&'static str),
 }
 
-async fn migrate_players() {
-    let pool = PG.get().await;
+/// Anything that only works with TimescaleDB. Migrations from before it was optional call it
+/// unmarked, and those steps are skipped on plain Postgres.
+const TIMESCALE_ONLY: &[&str] = &[
+    "timescaledb.",
+    "time_bucket(",
+    "create_hypertable(",
+    "add_retention_policy(",
+    "add_continuous_aggregate_policy(",
+    "add_compression_policy(",
+    "remove_compression_policy(",
+    "decompress_chunk(",
+];
 
-    println!("-> Migrating players");
+impl Step {
+    fn sql(&self, timescale: bool) -> Option<&'static str> {
+        match self {
+            Sql(sql) if timescale || !TIMESCALE_ONLY.iter().any(|call| sql.contains(call)) => {
+                Some(sql)
+            }
+            Plain(sql) if !timescale => Some(sql),
+            _ => None,
+        }
+    }
+}
 
-    println!("PLAYERS => DROP TABLE IF EXISTS players");
-    query("DROP TABLE IF EXISTS players")
-        .execute(pool)
-        .await
-        .unwrap();
+/// Every migration, oldest first. Only ever append to this; never edit a released migration.
+///
+/// Without TimescaleDB, migration 11 turns each hypertable into a table partitioned by range on
+/// its time column, with partitions the same size as Timescale's chunks, and migration 12 adds
+/// plain views in place of the continuous aggregates. `retention` creates upcoming partitions and
+/// `prune` drops old ones.
+static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "players_vehicles_analytics",
+        up: &[
+            Sql("CREATE TABLE IF NOT EXISTS players (
+            character_id TEXT NOT NULL PRIMARY KEY,
+            last_updated TIMESTAMPTZ NOT NULL,
+            world_id INT NOT NULL,
+            faction_id INT NOT NULL,
+            zone_id INT NOT NULL,
+            class_name TEXT NOT NULL);"),
+            Sql("CREATE TABLE IF NOT EXISTS vehicles (
+            character_id TEXT NOT NULL PRIMARY KEY,
+            last_updated TIMESTAMPTZ NOT NULL,
+            world_id INT NOT NULL,
+            faction_id INT NOT NULL,
+            zone_id INT NOT NULL,
+            vehicle_name TEXT NOT NULL);"),
+            Sql("CREATE TABLE IF NOT EXISTS analytics (
+            time TIMESTAMPTZ NOT NULL,
+            event_name TEXT NOT NULL,
+            world_id INT NOT NULL);"),
+            Sql("SELECT create_hypertable('analytics', 'time',
+                chunk_time_interval => INTERVAL '1 hour', if_not_exists => TRUE);"),
+            Sql("SELECT add_retention_policy('analytics', INTERVAL '1 day', if_not_exists => TRUE);"),
+        ],
+        down: Some(&[
+            Sql("DROP TABLE IF EXISTS analytics;"),
+            Sql("DROP TABLE IF EXISTS vehicles;"),
+            Sql("DROP TABLE IF EXISTS players;"),
+        ]),
+    },
+    Migration {
+        version: 2,
+        name: "vehicle_destroys",
+        up: &[
+            Sql("CREATE TABLE IF NOT EXISTS vehicle_destroys (
+            time TIMESTAMPTZ NOT NULL,
+            world_id INT NOT NULL,
+            zone_id INT NOT NULL,
+            faction_id INT NOT NULL,
+            vehicle_name TEXT NOT NULL,
+            attacker_faction_id INT NOT NULL,
+            attacker_vehicle_name TEXT NOT NULL);"),
+            Sql("SELECT create_hypertable('vehicle_destroys', 'time',
+                chunk_time_interval => INTERVAL '1 hour', if_not_exists => TRUE);"),
+            Sql("SELECT add_retention_policy('vehicle_destroys', INTERVAL '1 day', if_not_exists => TRUE);"),
+        ],
+        down: Some(&[Sql("DROP TABLE IF EXISTS vehicle_destroys;")]),
+    },
+    Migration {
+        version: 3,
+        name: "sessions",
+        up: &[
+            Sql("CREATE TABLE IF NOT EXISTS sessions (
+            character_id TEXT NOT NULL,
+            world_id INT NOT NULL,
+            faction_id INT NOT NULL,
+            first_seen TIMESTAMPTZ NOT NULL,
+            last_seen TIMESTAMPTZ NOT NULL);"),
+            Sql("SELECT create_hypertable('sessions', 'first_seen',
+                chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);"),
+            Sql("CREATE INDEX IF NOT EXISTS sessions_character_id_idx ON sessions (character_id, first_seen DESC);"),
+            Sql("SELECT add_retention_policy('sessions', INTERVAL '30 days', if_not_exists => TRUE);"),
+        ],
+        down: Some(&[Sql("DROP TABLE IF EXISTS sessions;")]),
+    },
+    Migration {
+        version: 4,
+        name: "characters_outfits",
+        up: &[
+            Sql("CREATE TABLE IF NOT EXISTS characters (
+            character_id TEXT NOT NULL PRIMARY KEY,
+            name TEXT,
+            outfit_id TEXT,
+            updated_at TIMESTAMPTZ NOT NULL);"),
+            Sql("CREATE TABLE IF NOT EXISTS outfits (
+            outfit_id TEXT NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            alias TEXT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL);"),
+            Sql("ALTER TABLE players ADD COLUMN IF NOT EXISTS outfit_id TEXT;"),
+        ],
+        down: Some(&[
+            Sql("ALTER TABLE players DROP COLUMN IF EXISTS outfit_id;"),
+            Sql("DROP TABLE IF EXISTS outfits;"),
+            Sql("DROP TABLE IF EXISTS characters;"),
+        ]),
+    },
+    Migration {
+        version: 5,
+        name: "platforms",
+        up: &[
+            Sql("ALTER TABLE players ADD COLUMN IF NOT EXISTS platform TEXT NOT NULL DEFAULT 'pc';"),
+            Sql("ALTER TABLE vehicles ADD COLUMN IF NOT EXISTS platform TEXT NOT NULL DEFAULT 'pc';"),
+            Sql("ALTER TABLE analytics ADD COLUMN IF NOT EXISTS platform TEXT NOT NULL DEFAULT 'pc';"),
+            Sql("ALTER TABLE vehicle_destroys ADD COLUMN IF NOT EXISTS platform TEXT NOT NULL DEFAULT 'pc';"),
+            Sql("ALTER TABLE sessions ADD COLUMN IF NOT EXISTS platform TEXT NOT NULL DEFAULT 'pc';"),
+        ],
+        down: Some(&[
+            Sql("ALTER TABLE sessions DROP COLUMN IF EXISTS platform;"),
+            Sql("ALTER TABLE vehicle_destroys DROP COLUMN IF EXISTS platform;"),
+            Sql("ALTER TABLE analytics DROP COLUMN IF EXISTS platform;"),
+            Sql("ALTER TABLE vehicles DROP COLUMN IF EXISTS platform;"),
+            Sql("ALTER TABLE players DROP COLUMN IF EXISTS platform;"),
+        ]),
+    },
+    Migration {
+        version: 6,
+        name: "vehicle_categories",
+        up: &[
+            Sql("ALTER TABLE vehicles ADD COLUMN IF NOT EXISTS category TEXT;"),
+            Sql("ALTER TABLE vehicles ADD COLUMN IF NOT EXISTS domain TEXT;"),
+        ],
+        down: Some(&[
+            Sql("ALTER TABLE vehicles DROP COLUMN IF EXISTS domain;"),
+            Sql("ALTER TABLE vehicles DROP COLUMN IF EXISTS category;"),
+        ]),
+    },
+    Migration {
+        version: 7,
+        name: "translators",
+        up: &[Sql("CREATE TABLE IF NOT EXISTS translators (
+            kind TEXT NOT NULL,
+            id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            PRIMARY KEY (kind, id));")],
+        down: Some(&[Sql("DROP TABLE IF EXISTS translators;")]),
+    },
+    Migration {
+        version: 8,
+        name: "population_history",
+        up: &[
+            Sql("CREATE TABLE IF NOT EXISTS population_history (
+            time TIMESTAMPTZ NOT NULL,
+            source TEXT NOT NULL,
+            world_id INT NOT NULL,
+            zone_id INT NOT NULL,
+            faction_id INT NOT NULL,
+            platform TEXT NOT NULL,
+            name TEXT NOT NULL,
+            count INT NOT NULL);"),
+            Sql("SELECT create_hypertable('population_history', 'time',
+                chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);"),
+            Sql("CREATE INDEX IF NOT EXISTS population_history_source_idx ON population_history (source, time DESC);"),
+            Sql("SELECT add_retention_policy('population_history', INTERVAL '30 days', if_not_exists => TRUE);"),
+        ],
+        down: Some(&[Sql("DROP TABLE IF EXISTS population_history;")]),
+    },
+    Migration {
+        version: 9,
+        name: "analytics_rollups",
+        up: &[
+            Sql("CREATE MATERIALIZED VIEW IF NOT EXISTS analytics_1m
+            WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+            SELECT time_bucket(INTERVAL '1 minute', time) AS bucket, event_name, world_id, platform, count(*) AS count
+            FROM analytics
+            GROUP BY bucket, event_name, world_id, platform
+            WITH NO DATA;"),
+            Sql("SELECT add_continuous_aggregate_policy('analytics_1m',
+                start_offset => INTERVAL '1 hour', end_offset => INTERVAL '1 minute',
+                schedule_interval => INTERVAL '1 minute', if_not_exists => TRUE);"),
+            Sql("CREATE MATERIALIZED VIEW IF NOT EXISTS analytics_1h
+            WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+            SELECT time_bucket(INTERVAL '1 hour', time) AS bucket, event_name, world_id, platform, count(*) AS count
+            FROM analytics
+            GROUP BY bucket, event_name, world_id, platform
+            WITH NO DATA;"),
+            Sql("SELECT add_continuous_aggregate_policy('analytics_1h',
+                start_offset => INTERVAL '3 hours', end_offset => INTERVAL '1 hour',
+                schedule_interval => INTERVAL '30 minutes', if_not_exists => TRUE);"),
+            Sql("ALTER TABLE analytics SET (timescaledb.compress,
+                timescaledb.compress_segmentby = 'event_name, world_id',
+                timescaledb.compress_orderby = 'time DESC');"),
+            Sql("SELECT add_compression_policy('analytics', INTERVAL '2 hours', if_not_exists => TRUE);"),
+        ],
+        down: Some(&[
+            Sql("SELECT remove_compression_policy('analytics', if_exists => TRUE);"),
+            Sql("SELECT decompress_chunk(chunk, if_compressed => TRUE) FROM show_chunks('analytics') chunk;"),
+            Sql("ALTER TABLE analytics SET (timescaledb.compress = false);"),
+            Sql("DROP MATERIALIZED VIEW IF EXISTS analytics_1h;"),
+            Sql("DROP MATERIALIZED VIEW IF EXISTS analytics_1m;"),
+        ]),
+    },
+    Migration {
+        version: 10,
+        name: "population_indexes",
+        // Room on each page keeps upserts HOT, and the index leaves out last_updated so it
+        // doesn't have to change on every upsert. hack/bench has the numbers behind this.
+        up: &[
+            Sql("ALTER TABLE players SET (fillfactor = 70);"),
+            Sql("ALTER TABLE vehicles SET (fillfactor = 70);"),
+            Sql("CREATE INDEX IF NOT EXISTS players_world_faction_zone_idx ON players (world_id, faction_id, zone_id);"),
+            Sql("CREATE INDEX IF NOT EXISTS vehicles_world_faction_zone_idx ON vehicles (world_id, faction_id, zone_id);"),
+        ],
+        down: Some(&[
+            Sql("DROP INDEX IF EXISTS vehicles_world_faction_zone_idx;"),
+            Sql("DROP INDEX IF EXISTS players_world_faction_zone_idx;"),
+            Sql("ALTER TABLE vehicles RESET (fillfactor);"),
+            Sql("ALTER TABLE players RESET (fillfactor);"),
+        ]),
+    },
+    Migration {
+        version: 11,
+        name: "plain_partitions",
+        // Only does anything without TimescaleDB. Copies each hypertable into a partitioned table,
+        // where it all lands in the default partition; `retention` adds the real partitions.
+        // The views are dropped first in case an older build made them, migration 12 remakes them.
+        up: &[
+            Plain("DROP VIEW IF EXISTS analytics_1h;"),
+            Plain("DROP VIEW IF EXISTS analytics_1m;"),
+            Plain("ALTER TABLE analytics RENAME TO analytics_unpartitioned;"),
+            Plain("CREATE TABLE analytics (LIKE analytics_unpartitioned INCLUDING DEFAULTS)
+                PARTITION BY RANGE (time);"),
+            Plain("CREATE TABLE analytics_default PARTITION OF analytics DEFAULT;"),
+            Plain("INSERT INTO analytics SELECT * FROM analytics_unpartitioned;"),
+            Plain("DROP TABLE analytics_unpartitioned;"),
+            Plain("CREATE INDEX IF NOT EXISTS analytics_time_idx ON analytics (time DESC);"),
+            Plain("ALTER TABLE vehicle_destroys RENAME TO vehicle_destroys_unpartitioned;"),
+            Plain("CREATE TABLE vehicle_destroys (LIKE vehicle_destroys_unpartitioned INCLUDING DEFAULTS)
+                PARTITION BY RANGE (time);"),
+            Plain("CREATE TABLE vehicle_destroys_default PARTITION OF vehicle_destroys DEFAULT;"),
+            Plain("INSERT INTO vehicle_destroys SELECT * FROM vehicle_destroys_unpartitioned;"),
+            Plain("DROP TABLE vehicle_destroys_unpartitioned;"),
+            Plain("CREATE INDEX IF NOT EXISTS vehicle_destroys_time_idx ON vehicle_destroys (time DESC);"),
+            Plain("ALTER TABLE sessions RENAME TO sessions_unpartitioned;"),
+            Plain("CREATE TABLE sessions (LIKE sessions_unpartitioned INCLUDING DEFAULTS)
+                PARTITION BY RANGE (first_seen);"),
+            Plain("CREATE TABLE sessions_default PARTITION OF sessions DEFAULT;"),
+            Plain("INSERT INTO sessions SELECT * FROM sessions_unpartitioned;"),
+            Plain("DROP TABLE sessions_unpartitioned;"),
+            Plain("CREATE INDEX IF NOT EXISTS sessions_first_seen_idx ON sessions (first_seen DESC);"),
+            Plain("CREATE INDEX IF NOT EXISTS sessions_character_id_idx ON sessions (character_id, first_seen DESC);"),
+            Plain("ALTER TABLE population_history RENAME TO population_history_unpartitioned;"),
+            Plain("CREATE TABLE population_history (LIKE population_history_unpartitioned INCLUDING DEFAULTS)
+                PARTITION BY RANGE (time);"),
+            Plain("CREATE TABLE population_history_default PARTITION OF population_history DEFAULT;"),
+            Plain("INSERT INTO population_history SELECT * FROM population_history_unpartitioned;"),
+            Plain("DROP TABLE population_history_unpartitioned;"),
+            Plain("CREATE INDEX IF NOT EXISTS population_history_time_idx ON population_history (time DESC);"),
+            Plain("CREATE INDEX IF NOT EXISTS population_history_source_idx ON population_history (source, time DESC);"),
+        ],
+        down: Some(&[
+            Plain("ALTER TABLE population_history RENAME TO population_history_partitioned;"),
+            Plain("CREATE TABLE population_history (LIKE population_history_partitioned INCLUDING DEFAULTS);"),
+            Plain("INSERT INTO population_history SELECT * FROM population_history_partitioned;"),
+            Plain("DROP TABLE population_history_partitioned;"),
+            Plain("CREATE INDEX IF NOT EXISTS population_history_source_idx ON population_history (source, time DESC);"),
+            Plain("ALTER TABLE sessions RENAME TO sessions_partitioned;"),
+            Plain("CREATE TABLE sessions (LIKE sessions_partitioned INCLUDING DEFAULTS);"),
+            Plain("INSERT INTO sessions SELECT * FROM sessions_partitioned;"),
+            Plain("DROP TABLE sessions_partitioned;"),
+            Plain("CREATE INDEX IF NOT EXISTS sessions_character_id_idx ON sessions (character_id, first_seen DESC);"),
+            Plain("ALTER TABLE vehicle_destroys RENAME TO vehicle_destroys_partitioned;"),
+            Plain("CREATE TABLE vehicle_destroys (LIKE vehicle_destroys_partitioned INCLUDING DEFAULTS);"),
+            Plain("INSERT INTO vehicle_destroys SELECT * FROM vehicle_destroys_partitioned;"),
+            Plain("DROP TABLE vehicle_destroys_partitioned;"),
+            Plain("ALTER TABLE analytics RENAME TO analytics_partitioned;"),
+            Plain("CREATE TABLE analytics (LIKE analytics_partitioned INCLUDING DEFAULTS);"),
+            Plain("INSERT INTO analytics SELECT * FROM analytics_partitioned;"),
+            Plain("DROP TABLE analytics_partitioned;"),
+        ]),
+    },
+    Migration {
+        version: 12,
+        name: "plain_rollups",
+        // Plain views stand in for the continuous aggregates from migration 9.
+        // `date_bin` lines up with `time_bucket`, which counts from the first Monday of 2000.
+        up: &[
+            Plain("CREATE OR REPLACE VIEW analytics_1m AS
+            SELECT date_bin(INTERVAL '1 minute', time, TIMESTAMPTZ '2000-01-03 00:00:00+00') AS bucket,
+                event_name, world_id, platform, count(*) AS count
+            FROM analytics
+            GROUP BY bucket, event_name, world_id, platform;"),
+            Plain("CREATE OR REPLACE VIEW analytics_1h AS
+            SELECT date_bin(INTERVAL '1 hour', time, TIMESTAMPTZ '2000-01-03 00:00:00+00') AS bucket,
+                event_name, world_id, platform, count(*) AS count
+            FROM analytics
+            GROUP BY bucket, event_name, world_id, platform;"),
+        ],
+        down: Some(&[
+            Plain("DROP VIEW IF EXISTS analytics_1h;"),
+            Plain("DROP VIEW IF EXISTS analytics_1m;"),
+        ]),
+    },
+    Migration {
+        version: 13,
+        name: "population_last_updated_indexes",
+        // Led by last_updated, so short windows only read the rows seen in them (a 1 minute
+        // window is about twice as fast at 250k players). Costs HOT updates, since last_updated
+        // changes on every upsert. hack/bench has the numbers behind this.
+        up: &[
+            Sql("CREATE INDEX IF NOT EXISTS players_last_updated_idx ON players (last_updated, world_id, faction_id, zone_id);"),
+            Sql("CREATE INDEX IF NOT EXISTS vehicles_last_updated_idx ON vehicles (last_updated, world_id, faction_id, zone_id);"),
+        ],
+        down: Some(&[
+            Sql("DROP INDEX IF EXISTS vehicles_last_updated_idx;"),
+            Sql("DROP INDEX IF EXISTS players_last_updated_idx;"),
+        ]),
+    },
+];
+
+/// A table (or view) as it should look once every migration has run.
+pub struct ExpectedTable {
+    pub name: &'static str,
+    /// Column names and their Postgres type names (`udt_name`), in any order.
+    pub columns: &'static [(&'static str, &'static str)],
+    pub kind: TableKind,
+    pub indexes: &'static [&'static str],
+}
+
+#[derive(PartialEq)]
+pub enum TableKind {
+    Table,
+    /// A partitioned table without TimescaleDB.
+    Hypertable,
+    /// A plain view without TimescaleDB.
+    ContinuousAggregate,
+}
+
+/// The shape `tasks doctor` checks the database against. Keep it in step with MIGRATIONS.
+pub static EXPECTED_TABLES: &[ExpectedTable] = &[
+    ExpectedTable {
+        name: "players",
+        columns: &[
+            ("character_id", "text"),
+            ("last_updated", "timestamptz"),
+            ("world_id", "int4"),
+            ("faction_id", "int4"),
+            ("zone_id", "int4"),
+            ("class_name", "text"),
+            ("outfit_id", "text"),
+            ("platform", "text"),
+        ],
+        kind: TableKind::Table,
+        indexes: &[
+            "players_pkey",
+            "players_world_faction_zone_idx",
+            "players_last_updated_idx",
+        ],
+    },
+    ExpectedTable {
+        name: "vehicles",
+        columns: &[
+            ("character_id", "text"),
+            ("last_updated", "timestamptz"),
+            ("world_id", "int4"),
+            ("faction_id", "int4"),
+            ("zone_id", "int4"),
+            ("vehicle_name", "text"),
+            ("platform", "text"),
+            ("category", "text"),
+            ("domain", "text"),
+        ],
+        kind: TableKind::Table,
+        indexes: &[
+            "vehicles_pkey",
+            "vehicles_world_faction_zone_idx",
+            "vehicles_last_updated_idx",
+        ],
+    },
+    ExpectedTable {
+        name: "analytics",
+        columns: &[
+            ("time", "timestamptz"),
+            ("event_name", "text"),
+            ("world_id", "int4"),
+            ("platform", "text"),
+        ],
+        kind: TableKind::Hypertable,
+        indexes: &["analytics_time_idx"],
+    },
+    ExpectedTable {
+        name: "vehicle_destroys",
+        columns: &[
+            ("time", "timestamptz"),
+            ("world_id", "int4"),
+            ("zone_id", "int4"),
+            ("faction_id", "int4"),
+            ("vehicle_name", "text"),
+            ("attacker_faction_id", "int4"),
+            ("attacker_vehicle_name", "text"),
+            ("platform", "text"),
+        ],
+        kind: TableKind::Hypertable,
+        indexes: &["vehicle_destroys_time_idx"],
+    },
+    ExpectedTable {
+        name: "sessions",
+        columns: &[
+            ("character_id", "text"),
+            ("world_id", "int4"),
+            ("faction_id", "int4"),
+            ("first_seen", "timestamptz"),
+            ("last_seen", "timestamptz"),
+            ("platform", "text"),
+        ],
+        kind: TableKind::Hypertable,
+        indexes: &["sessions_character_id_idx", "sessions_first_seen_idx"],
+    },
+    ExpectedTable {
+        name: "characters",
+        columns: &[
+            ("character_id", "text"),
+            ("name", "text"),
+            ("outfit_id", "text"),
+            ("updated_at", "timestamptz"),
+        ],
+        kind: TableKind::Table,
+        indexes: &["characters_pkey"],
+    },
+    ExpectedTable {
+        name: "outfits",
+        columns: &[
+            ("outfit_id", "text"),
+            ("name", "text"),
+            ("alias", "text"),
+            ("updated_at", "timestamptz"),
+        ],
+        kind: TableKind::Table,
+        indexes: &["outfits_pkey"],
+    },
+    ExpectedTable {
+        name: "translators",
+        columns: &[("kind", "text"), ("id", "text"), ("name", "text")],
+        kind: TableKind::Table,
+        indexes: &["translators_pkey"],
+    },
+    ExpectedTable {
+        name: "population_history",
+        columns: &[
+            ("time", "timestamptz"),
+            ("source", "text"),
+            ("world_id", "int4"),
+            ("zone_id", "int4"),
+            ("faction_id", "int4"),
+            ("platform", "text"),
+            ("name", "text"),
+            ("count", "int4"),
+        ],
+        kind: TableKind::Hypertable,
+        indexes: &[
+            "population_history_time_idx",
+            "population_history_source_idx",
+        ],
+    },
+    ExpectedTable {
+        name: "analytics_1m",
+        columns: &[
+            ("bucket", "timestamptz"),
+            ("event_name", "text"),
+            ("world_id", "int4"),
+            ("platform", "text"),
+            ("count", "int8"),
+        ],
+        kind: TableKind::ContinuousAggregate,
+        indexes: &[],
+    },
+    ExpectedTable {
+        name: "analytics_1h",
+        columns: &[
+            ("bucket", "timestamptz"),
+            ("event_name", "text"),
+            ("world_id", "int4"),
+            ("platform", "text"),
+            ("count", "int8"),
+        ],
+        kind: TableKind::ContinuousAggregate,
+        indexes: &[],
+    },
+];
+
+/// Any two tasks running migrations at once wait on each other with this.
+const MIGRATION_LOCK: i64 = 0x5ae220;
+
+pub async fn cmd_migrate(subcommand: Option<String>) {
+    match subcommand.as_deref() {
+        None | Some("up") => {
+            println!("Migrating database...");
+            migrate_pending().await;
+        }
+        Some("status") => cmd_status().await,
+        Some("down") => cmd_down().await,
+        Some(other) => {
+            println!("Unknown migrate command: {}", other);
+            println!("Usage: migrate [up|status|down]");
+        }
+    }
+}
+
+async fn ensure_schema_migrations() {
+    let pool = PG.get().await;
 
-    println!("PLAYERS => CREATE TABLE players");
     query(
-        "CREATE TABLE players (
-        character_id TEXT NOT NULL PRIMARY KEY,
-        last_updated TIMESTAMPTZ NOT NULL,
-        world_id INT NOT NULL,
-        faction_id INT NOT NULL,
-        zone_id INT NOT NULL,
-        class_name TEXT NOT NULL
-        );",
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+        version BIGINT NOT NULL PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT now());",
     )
     .execute(pool)
     .await
     .unwrap();
+}
 
-    println!("PLAYERS => done!");
+/// Versions that have been applied and when, oldest first.
+async fn applied_versions() -> Vec<(i64, String)> {
+    let pool = PG.get().await;
+
+    ensure_schema_migrations().await;
+    query("SELECT version, applied_at::text FROM schema_migrations ORDER BY version;")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
 }
 
-async fn migrate_vehicles() {
+/// Versions and names of migrations that haven't been applied, without creating
+/// `schema_migrations`. None if it doesn't exist yet.
+pub async fn pending_migrations() -> Option<Vec<(i64, &'static str)>> {
     let pool = PG.get().await;
 
-    println!("-> Migrating vehicles");
+    let exists: bool = query("SELECT to_regclass('schema_migrations') IS NOT NULL;")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .get(0);
+    if !exists {
+        return None;
+    }
 
-    println!("VEHICLES => DROP TABLE IF EXISTS vehicles");
-    query("DROP TABLE IF EXISTS vehicles")
-        .execute(pool)
+    let applied: Vec<i64> = query("SELECT version FROM schema_migrations;")
+        .fetch_all(pool)
         .await
-        .unwrap();
+        .unwrap()
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
 
-    println!("VEHICLES => CREATE TABLE vehicles");
-    query(
-        "CREATE TABLE vehicles (
-        character_id TEXT NOT NULL PRIMARY KEY,
-        last_updated TIMESTAMPTZ NOT NULL,
-        world_id INT NOT NULL,
-        faction_id INT NOT NULL,
-        zone_id INT NOT NULL,
-        vehicle_name TEXT NOT NULL
-        );",
+    Some(
+        MIGRATIONS
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .map(|migration| (migration.version, migration.name))
+            .collect(),
     )
-    .execute(pool)
-    .await
-    .unwrap();
+}
+
+/// Applies every migration that hasn't been applied yet, returning how many were applied.
+/// Does nothing if the database is up to date.
+pub async fn migrate_pending() -> u64 {
+    let pool = PG.get().await;
 
-    println!("VEHICLES => done!");
+    let applied: Vec<i64> = applied_versions()
+        .await
+        .into_iter()
+        .map(|(version, _)| version)
+        .collect();
+
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect();
+
+    if pending.is_empty() {
+        println!("MIGRATIONS => up to date");
+        return 0;
+    }
+
+    let timescale = has_timescale().await;
+    let mut count = 0;
+    for migration in pending {
+        let mut tx = pool.begin().await.unwrap();
+
+        query("SELECT pg_advisory_xact_lock($1);")
+            .bind(MIGRATION_LOCK)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        // Someone else may have applied it while we waited for the lock
+        let already_applied: bool =
+            query("SELECT EXISTS (SELECT 1 FROM schema_migrations WHERE version = $1);")
+                .bind(migration.version)
+                .fetch_one(&mut *tx)
+                .await
+                .unwrap()
+                .get(0);
+        if already_applied {
+            continue;
+        }
+
+        println!("MIGRATIONS => {} {} up", migration.version, migration.name);
+        for step in migration.up.iter().filter_map(|step| step.sql(timescale)) {
+            query(step).execute(&mut *tx).await.unwrap();
+        }
+
+        query("INSERT INTO schema_migrations (version, name) VALUES ($1, $2);")
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        tx.commit().await.unwrap();
+        count += 1;
+    }
+
+    println!("MIGRATIONS => done!");
+    count
 }
 
-async fn migrate_analytics() {
+/// Rolls back the most recently applied migration.
+async fn cmd_down() {
     let pool = PG.get().await;
 
-    println!("-> Migrating analytics");
-    println!("ANALYTICS => CREATE TABLE IF NOT EXISTS analytics");
-    query(
-        "CREATE TABLE IF NOT EXISTS analytics (
-        time TIMESTAMPTZ NOT NULL,
-        event_name TEXT NOT NULL,
-        world_id INT NOT NULL);",
-    )
-    .execute(pool)
-    .await
-    .unwrap();
+    let latest = match applied_versions().await.last() {
+        Some((version, _)) => *version,
+        None => {
+            println!("MIGRATIONS => nothing to roll back");
+            return;
+        }
+    };
 
-    println!("ANALYTICS => create_hypertable");
-    query(
-        "SELECT create_hypertable('analytics', 'time', 
-            chunk_time_interval => INTERVAL '1 hour', if_not_exists => TRUE);",
-    )
-    .execute(pool)
-    .await
-    .unwrap();
+    let migration = match MIGRATIONS
+        .iter()
+        .find(|migration| migration.version == latest)
+    {
+        Some(migration) => migration,
+        None => {
+            println!(
+                "MIGRATIONS => {} was applied by a newer version of tasks, not rolling back",
+                latest
+            );
+            return;
+        }
+    };
+
+    let down = match migration.down {
+        Some(down) => down,
+        None => {
+            println!(
+                "MIGRATIONS => {} {} can't be rolled back",
+                migration.version, migration.name
+            );
+            return;
+        }
+    };
+
+    let mut tx = pool.begin().await.unwrap();
+
+    query("SELECT pg_advisory_xact_lock($1);")
+        .bind(MIGRATION_LOCK)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+    println!(
+        "MIGRATIONS => {} {} down",
+        migration.version, migration.name
+    );
+    let timescale = has_timescale().await;
+    for step in down.iter().filter_map(|step| step.sql(timescale)) {
+        query(step).execute(&mut *tx).await.unwrap();
+    }
 
-    println!("ANALYTICS => add_retention_policy");
-    query("SELECT add_retention_policy('analytics', INTERVAL '1 day', if_not_exists => TRUE);")
-        .execute(pool)
+    query("DELETE FROM schema_migrations WHERE version = $1;")
+        .bind(migration.version)
+        .execute(&mut *tx)
         .await
         .unwrap();
 
-    println!("ANALYTICS => done!");
+    tx.commit().await.unwrap();
+
+    println!("MIGRATIONS => done!");
 }
 
-pub async fn is_migrated() -> bool {
-    let pool = PG.get().await;
+async fn cmd_status() {
+    let applied = applied_versions().await;
 
-    let tables: i64 = query("SELECT count(1) FROM pg_tables WHERE schemaname = 'public' AND tablename IN ('players', 'vehicles', 'analytics');")
-        .fetch_one(pool)
-        .await
-        .unwrap()
-        .get(0);
+    for migration in MIGRATIONS {
+        match applied
+            .iter()
+            .find(|(version, _)| *version == migration.version)
+        {
+            Some((_, applied_at)) => println!(
+                "  applied  {:>4} {} ({})",
+                migration.version, migration.name, applied_at
+            ),
+            None => println!("  pending  {:>4} {}", migration.version, migration.name),
+        }
+    }
 
-    tables == 3
+    // Applied by a newer version of tasks than this one
+    for (version, applied_at) in applied.iter() {
+        if !MIGRATIONS
+            .iter()
+            .any(|migration| migration.version == *version)
+        {
+            println!("  unknown  {:>4} ({})", version, applied_at);
+        }
+    }
 }
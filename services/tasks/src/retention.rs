@@ -0,0 +1,502 @@
+use crate::{has_timescale, PG};
+use chrono::{DateTime, Duration, DurationRound, NaiveDateTime, TimeZone, Utc};
+use sqlx::{query, Row};
+
+/// How long rows in a table are kept, and the column that decides their age.
+struct RetentionRule {
+    table: &'static str,
+    time_column: &'static str,
+    /// Postgres interval used when `RETENTION_<TABLE>` isn't set.
+    default: &'static str,
+    storage: Storage,
+}
+
+#[derive(PartialEq)]
+enum Storage {
+    /// Pruned by `prune` only.
+    Table,
+    /// Pruned by `prune`, and also gets a Timescale retention policy with the same interval.
+    /// Without TimescaleDB it's partitioned by its time column, one partition per span.
+    Hypertable(Span),
+    /// Can't be deleted from, so only its Timescale retention policy removes old rows.
+    /// Without TimescaleDB it's a view over its source, so it goes back as far as that does.
+    ContinuousAggregate,
+}
+
+/// How much time one partition covers, the same as the table's Timescale chunks.
+#[derive(PartialEq)]
+enum Span {
+    Hour,
+    Day,
+}
+
+impl Span {
+    fn duration(&self) -> Duration {
+        match self {
+            Span::Hour => Duration::hours(1),
+            Span::Day => Duration::days(1),
+        }
+    }
+
+    /// How many partitions after the current one are kept ready, so new rows don't end up in
+    /// the default partition.
+    fn ahead(&self) -> i32 {
+        match self {
+            Span::Hour => 24,
+            Span::Day => 2,
+        }
+    }
+
+    /// The partition of `table` starting at `start`, like `analytics_p2024_01_31_13`.
+    fn partition(&self, table: &str, start: DateTime<Utc>) -> String {
+        match self {
+            Span::Hour => format!("{}_p{}", table, start.format("%Y_%m_%d_%H")),
+            Span::Day => format!("{}_p{}", table, start.format("%Y_%m_%d")),
+        }
+    }
+
+    /// Where the partition named `name` starts, or None if it isn't one of `table`'s.
+    fn start_of(&self, table: &str, name: &str) -> Option<DateTime<Utc>> {
+        let stamp = name.strip_prefix(table)?.strip_prefix("_p")?;
+        let start = match self {
+            Span::Hour => NaiveDateTime::parse_from_str(&format!("{}_00", stamp), "%Y_%m_%d_%H_%M"),
+            Span::Day => {
+                NaiveDateTime::parse_from_str(&format!("{}_00_00", stamp), "%Y_%m_%d_%H_%M")
+            }
+        };
+        start.ok().map(|start| Utc.from_utc_datetime(&start))
+    }
+
+    /// The start of the partition `time` falls in.
+    fn floor(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        time.duration_trunc(self.duration()).unwrap()
+    }
+}
+
+static RULES: &[RetentionRule] = &[
+    RetentionRule {
+        table: "players",
+        time_column: "last_updated",
+        default: "15 minutes",
+        storage: Storage::Table,
+    },
+    RetentionRule {
+        table: "vehicles",
+        time_column: "last_updated",
+        default: "15 minutes",
+        storage: Storage::Table,
+    },
+    RetentionRule {
+        table: "analytics",
+        time_column: "time",
+        default: "1 day",
+        storage: Storage::Hypertable(Span::Hour),
+    },
+    RetentionRule {
+        table: "vehicle_destroys",
+        time_column: "time",
+        default: "1 day",
+        storage: Storage::Hypertable(Span::Hour),
+    },
+    RetentionRule {
+        table: "sessions",
+        time_column: "first_seen",
+        default: "30 days",
+        storage: Storage::Hypertable(Span::Day),
+    },
+    RetentionRule {
+        table: "population_history",
+        time_column: "time",
+        default: "30 days",
+        storage: Storage::Hypertable(Span::Day),
+    },
+    RetentionRule {
+        table: "analytics_1m",
+        time_column: "bucket",
+        default: "7 days",
+        storage: Storage::ContinuousAggregate,
+    },
+    RetentionRule {
+        table: "analytics_1h",
+        time_column: "bucket",
+        default: "1 year",
+        storage: Storage::ContinuousAggregate,
+    },
+];
+
+impl RetentionRule {
+    /// The configured interval, from `RETENTION_<TABLE>` (like `RETENTION_ANALYTICS="7 days"`).
+    fn interval(&self) -> String {
+        std::env::var(format!("RETENTION_{}", self.table.to_uppercase()))
+            .unwrap_or(self.default.to_string())
+    }
+}
+
+/// The configured retention of `table`, like `15 minutes`.
+pub fn interval(table: &str) -> String {
+    RULES
+        .iter()
+        .find(|rule| rule.table == table)
+        .unwrap()
+        .interval()
+}
+
+/// Deletes rows older than each table's retention. Hypertables with a Timescale retention policy
+/// are left to it, since old chunks may be compressed. Without TimescaleDB, hypertables drop whole
+/// partitions instead, like Timescale drops chunks. With `dry_run`, only counts them, and also
+/// counts what each retention policy will drop, labelled as such and left out of the total.
+/// Returns the total rows deleted (or that would be).
+pub async fn cmd_prune(dry_run: bool) -> u64 {
+    if dry_run {
+        println!("Pruning old data (dry run, nothing is deleted)...");
+    } else {
+        println!("Pruning old data...");
+    }
+    let timescale = has_timescale().await;
+    if !timescale && !dry_run {
+        ensure_partitions().await;
+    }
+    let mut total = 0;
+
+    for rule in RULES
+        .iter()
+        .filter(|rule| rule.storage != Storage::ContinuousAggregate)
+    {
+        let interval = rule.interval();
+
+        if timescale
+            && matches!(rule.storage, Storage::Hypertable(_))
+            && current_policy(rule.table).await.is_some()
+        {
+            if dry_run {
+                // Not added to the total, since prune itself won't delete them
+                let rows = count_older(rule.table, rule.time_column, &interval).await;
+                println!(
+                    "Would skip {} rows of {} older than {} (handled by policy)",
+                    rows, rule.table, interval
+                );
+            } else {
+                println!(
+                    "Skipping {}, its Timescale retention policy drops old chunks",
+                    rule.table
+                );
+            }
+            continue;
+        }
+
+        let partitions = match &rule.storage {
+            Storage::Hypertable(span) if !timescale => partitions(rule.table)
+                .await
+                .map(|partitions| (span, partitions)),
+            _ => None,
+        };
+
+        match partitions {
+            Some((span, partitions)) => {
+                total += drop_partitions(rule.table, span, &partitions, &interval, dry_run).await;
+                // Rows from before their partition existed
+                let default = format!("{}_default", rule.table);
+                total += delete_older(&default, rule.time_column, &interval, dry_run).await;
+            }
+            None => total += delete_older(rule.table, rule.time_column, &interval, dry_run).await,
+        }
+    }
+
+    total
+}
+
+/// Rows of `table` older than `interval`.
+async fn count_older(table: &str, time_column: &str, interval: &str) -> u64 {
+    let rows: i64 = query(
+        format!(
+            "SELECT count(*) FROM {} WHERE {} < now() - $1::interval;",
+            table, time_column
+        )
+        .as_str(),
+    )
+    .bind(interval)
+    .fetch_one(PG.get().await)
+    .await
+    .unwrap()
+    .get(0);
+    rows as u64
+}
+
+async fn delete_older(table: &str, time_column: &str, interval: &str, dry_run: bool) -> u64 {
+    if dry_run {
+        let rows = count_older(table, time_column, interval).await;
+        println!(
+            "Would delete {} rows of {} older than {}",
+            rows, table, interval
+        );
+        rows
+    } else {
+        let pool = PG.get().await;
+        let filter = format!(
+            "FROM {} WHERE {} < now() - $1::interval",
+            table, time_column
+        );
+        // A hypertable without a policy may still have compressed chunks, which can't be
+        // deleted from, so that's reported and the other tables are still pruned
+        match query(format!("DELETE {};", filter).as_str())
+            .bind(interval)
+            .execute(pool)
+            .await
+        {
+            Ok(result) => {
+                let rows = result.rows_affected();
+                println!("Deleted {} rows of {} older than {}", rows, table, interval);
+                rows
+            }
+            Err(e) => {
+                println!("[retention/delete_older] ERR => {}: {:?}", table, e);
+                0
+            }
+        }
+    }
+}
+
+/// Drops the partitions of `table` that only hold rows older than `interval`.
+async fn drop_partitions(
+    table: &str,
+    span: &Span,
+    partitions: &[String],
+    interval: &str,
+    dry_run: bool,
+) -> u64 {
+    let pool = PG.get().await;
+    let cutoff: DateTime<Utc> = query("SELECT now() - $1::interval;")
+        .bind(interval)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .get(0);
+    let mut total = 0;
+
+    for partition in partitions {
+        match span.start_of(table, partition) {
+            Some(start) if start + span.duration() <= cutoff => (),
+            _ => continue,
+        }
+
+        let rows: i64 = query(format!("SELECT count(*) FROM {};", partition).as_str())
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .get(0);
+        if dry_run {
+            println!(
+                "Would drop {} ({} rows) older than {}",
+                partition, rows, interval
+            );
+        } else {
+            query(format!("DROP TABLE {};", partition).as_str())
+                .execute(pool)
+                .await
+                .unwrap();
+            println!(
+                "Dropped {} ({} rows) older than {}",
+                partition, rows, interval
+            );
+        }
+        total += rows as u64;
+    }
+
+    total
+}
+
+/// The partitions of `table`, or None if it isn't partitioned.
+async fn partitions(table: &str) -> Option<Vec<String>> {
+    let pool = PG.get().await;
+
+    let partitioned: bool = query(
+        "SELECT EXISTS (SELECT 1 FROM pg_partitioned_table WHERE partrelid = to_regclass($1));",
+    )
+    .bind(table)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+    .get(0);
+    if !partitioned {
+        return None;
+    }
+
+    Some(
+        query("SELECT inhrelid::regclass::text FROM pg_inherits WHERE inhparent = $1::regclass;")
+            .bind(table)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect(),
+    )
+}
+
+/// Without TimescaleDB, creates each hypertable's partitions from the current one to
+/// `Span::ahead` past it. Rows already in the default partition for that time are moved in.
+pub async fn ensure_partitions() {
+    let pool = PG.get().await;
+
+    for rule in RULES.iter() {
+        let span = match &rule.storage {
+            Storage::Hypertable(span) => span,
+            _ => continue,
+        };
+        let existing = match partitions(rule.table).await {
+            Some(existing) => existing,
+            None => continue,
+        };
+
+        let current = span.floor(Utc::now());
+        for n in 0..=span.ahead() {
+            let start = current + span.duration() * n;
+            let partition = span.partition(rule.table, start);
+            if existing.contains(&partition) {
+                continue;
+            }
+
+            let range = format!(
+                "{} >= '{}' AND {} < '{}'",
+                rule.time_column,
+                start.to_rfc3339(),
+                rule.time_column,
+                (start + span.duration()).to_rfc3339()
+            );
+            let steps = [
+                format!(
+                    "CREATE TABLE {} (LIKE {} INCLUDING DEFAULTS);",
+                    partition, rule.table
+                ),
+                format!(
+                    "INSERT INTO {} SELECT * FROM {}_default WHERE {};",
+                    partition, rule.table, range
+                ),
+                format!("DELETE FROM {}_default WHERE {};", rule.table, range),
+                format!(
+                    "ALTER TABLE {} ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}');",
+                    rule.table,
+                    partition,
+                    start.to_rfc3339(),
+                    (start + span.duration()).to_rfc3339()
+                ),
+            ];
+
+            let mut tx = pool.begin().await.unwrap();
+            for step in steps.iter() {
+                query(step.as_str()).execute(&mut *tx).await.unwrap();
+            }
+            tx.commit().await.unwrap();
+            println!("RETENTION => {} partition {}", rule.table, partition);
+        }
+    }
+}
+
+/// The `drop_after` of a table's Timescale retention policy, if it has one.
+pub async fn current_policy(table: &str) -> Option<String> {
+    let pool = PG.get().await;
+
+    query(
+        "SELECT config->>'drop_after' FROM timescaledb_information.jobs
+        WHERE proc_name = 'policy_retention' AND (
+            hypertable_name = $1
+            -- a continuous aggregate's policies are on the hypertable behind it
+            OR hypertable_name IN (SELECT materialization_hypertable_name
+                FROM timescaledb_information.continuous_aggregates WHERE view_name = $1)
+        );",
+    )
+    .bind(table)
+    .fetch_optional(pool)
+    .await
+    .unwrap()
+    .map(|row| row.get(0))
+}
+
+pub async fn same_interval(a: &str, b: &str) -> bool {
+    query("SELECT $1::interval = $2::interval;")
+        .bind(a)
+        .bind(b)
+        .fetch_one(PG.get().await)
+        .await
+        .unwrap()
+        .get(0)
+}
+
+/// Tables with a Timescale retention policy, and the interval it should have.
+pub fn policies() -> Vec<(&'static str, String)> {
+    RULES
+        .iter()
+        .filter(|rule| rule.storage != Storage::Table)
+        .map(|rule| (rule.table, rule.interval()))
+        .collect()
+}
+
+/// Makes each hypertable's and continuous aggregate's Timescale retention policy match its
+/// configured interval, replacing the policy when the interval changed. Without TimescaleDB,
+/// where `prune` is the only retention, it makes sure upcoming partitions exist instead.
+pub async fn sync_policies() {
+    let pool = PG.get().await;
+
+    if !has_timescale().await {
+        ensure_partitions().await;
+        return;
+    }
+
+    for rule in RULES.iter().filter(|rule| rule.storage != Storage::Table) {
+        let interval = rule.interval();
+
+        let current = current_policy(rule.table).await;
+        let up_to_date = match &current {
+            Some(current) => same_interval(current, &interval).await,
+            None => false,
+        };
+
+        if up_to_date {
+            continue;
+        }
+
+        println!(
+            "RETENTION => {} policy {} -> {}",
+            rule.table,
+            current.as_deref().unwrap_or("none"),
+            interval
+        );
+
+        query("SELECT remove_retention_policy($1::regclass, if_exists => TRUE);")
+            .bind(rule.table)
+            .execute(pool)
+            .await
+            .unwrap();
+        query("SELECT add_retention_policy($1::regclass, $2::interval);")
+            .bind(rule.table)
+            .bind(&interval)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+}
+
+/// Prints each table's retention.
+pub fn print_rules(timescale: bool) {
+    if !timescale {
+        println!("TimescaleDB isn't installed, so there are no retention policies and prune does it all.");
+        println!("Hypertables are partitioned by time instead, and prune drops partitions past their retention.");
+    }
+
+    for rule in RULES {
+        println!(
+            "  {:<18} {:<12} by {}{}",
+            rule.table,
+            rule.interval(),
+            rule.time_column,
+            match (&rule.storage, timescale) {
+                (Storage::Table, _) => "",
+                (Storage::Hypertable(_), true) => " (+ Timescale policy)",
+                (Storage::Hypertable(_), false) => " (drops whole partitions)",
+                (Storage::ContinuousAggregate, true) => " (Timescale policy only)",
+                (Storage::ContinuousAggregate, false) =>
+                    " (a view, follows its source's retention)",
+            }
+        );
+    }
+}
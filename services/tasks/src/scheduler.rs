@@ -0,0 +1,367 @@
+use crate::telemetry;
+use axum::{
+    extract::Path,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::{sync::Notify, task::JoinError};
+
+pub type JobFuture = Pin<Box<dyn Future<Output = u64> + Send>>;
+
+/// A named job the scheduler runs. `run` returns how many rows it affected.
+pub struct Job {
+    pub name: &'static str,
+    /// Used when `JOB_<NAME>_SCHEDULE` isn't set.
+    pub default_schedule: &'static str,
+    pub run: fn() -> JobFuture,
+}
+
+lazy_static! {
+    static ref STATUS: Mutex<BTreeMap<&'static str, JobStatus>> = Mutex::new(BTreeMap::new());
+    static ref TRIGGERS: Mutex<BTreeMap<&'static str, Arc<Notify>>> = Mutex::new(BTreeMap::new());
+    /// Triggering a job needs `Authorization: Bearer <ADMIN_TOKEN>`, and is off when it's unset.
+    static ref ADMIN_TOKEN: String = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+}
+
+/// Either a plain interval (`30s`, `5m`, `1h`) or a cron expression with seconds
+/// (`0 */5 * * * *`). Intervals run right away on startup, cron waits for its first match.
+enum Schedule {
+    Every(chrono::Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    fn parse(raw: &str) -> Result<Schedule, String> {
+        let raw = raw.trim();
+        if raw.contains(' ') {
+            return cron::Schedule::from_str(raw)
+                .map(|schedule| Schedule::Cron(Box::new(schedule)))
+                .map_err(|e| format!("bad cron expression {:?}: {}", raw, e));
+        }
+
+        let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+        let (number, unit) = raw.split_at(split);
+        let number: i64 = number
+            .parse()
+            .map_err(|_| format!("bad interval {:?}", raw))?;
+
+        let interval = match unit {
+            "" | "s" => chrono::Duration::seconds(number),
+            "m" => chrono::Duration::minutes(number),
+            "h" => chrono::Duration::hours(number),
+            _ => return Err(format!("bad interval {:?}, use s, m, or h", raw)),
+        };
+
+        if number == 0 {
+            return Err(format!("bad interval {:?}, must be more than 0", raw));
+        }
+
+        Ok(Schedule::Every(interval))
+    }
+
+    fn next_after(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Schedule::Every(interval) => time + *interval,
+            Schedule::Cron(schedule) => schedule
+                .after(&time)
+                .next()
+                .unwrap_or(time + chrono::Duration::days(365)),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct JobStatus {
+    name: &'static str,
+    schedule: String,
+    running: bool,
+    next_run: Option<DateTime<Utc>>,
+    last_started: Option<DateTime<Utc>>,
+    last_finished: Option<DateTime<Utc>>,
+    last_duration_ms: Option<u128>,
+    last_rows_affected: Option<u64>,
+    last_error: Option<String>,
+    /// Failures in a row, reset by a successful run.
+    consecutive_failures: u64,
+}
+
+fn update(name: &'static str, f: impl FnOnce(&mut JobStatus)) {
+    if let Some(status) = STATUS.lock().unwrap().get_mut(name) {
+        f(status);
+    }
+}
+
+fn panic_message(e: JoinError) -> String {
+    if !e.is_panic() {
+        return "cancelled".to_string();
+    }
+
+    let panic = e.into_panic();
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked".to_string()
+    }
+}
+
+/// Runs a job once. It runs on its own task, so a panic only fails this run.
+async fn run_once(job: &'static Job) {
+    println!("[scheduler/{}] Running", job.name);
+    update(job.name, |status| {
+        status.running = true;
+        status.next_run = None;
+        status.last_started = Some(Utc::now());
+    });
+
+    let started = Instant::now();
+    let result = tokio::spawn((job.run)()).await;
+    let elapsed = started.elapsed();
+
+    match result {
+        Ok(rows) => {
+            println!(
+                "[scheduler/{}] Done in {}ms, {} rows affected",
+                job.name,
+                elapsed.as_millis(),
+                rows
+            );
+            telemetry::job_succeeded(job.name, elapsed.as_secs_f64(), rows);
+            update(job.name, |status| {
+                status.last_rows_affected = Some(rows);
+                status.last_error = None;
+                status.consecutive_failures = 0;
+            });
+        }
+        Err(e) => {
+            let message = panic_message(e);
+            println!("[scheduler/{}] ERR => {}", job.name, message);
+            telemetry::job_failed(job.name, elapsed.as_secs_f64());
+            update(job.name, |status| {
+                status.last_error = Some(message);
+                status.consecutive_failures += 1;
+            });
+        }
+    }
+
+    update(job.name, |status| {
+        status.running = false;
+        status.last_finished = Some(Utc::now());
+        status.last_duration_ms = Some(elapsed.as_millis());
+    });
+}
+
+async fn job_loop(job: &'static Job, schedule: Schedule, trigger: Arc<Notify>) {
+    let mut next = match schedule {
+        Schedule::Every(_) => Utc::now(),
+        Schedule::Cron(_) => schedule.next_after(Utc::now()),
+    };
+
+    loop {
+        update(job.name, |status| status.next_run = Some(next));
+
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = trigger.notified() => {
+                println!("[scheduler/{}] Triggered", job.name);
+            }
+        }
+
+        run_once(job).await;
+        next = schedule.next_after(Utc::now());
+    }
+}
+
+/// Runs the given jobs on their schedules forever, and serves /healthz, /metrics,
+/// and POST /jobs/:name/run on PORT (default 8998).
+pub async fn run(jobs: Vec<&'static Job>) {
+    for job in jobs {
+        let env_name = format!("JOB_{}_SCHEDULE", job.name.to_uppercase());
+        let raw = std::env::var(&env_name).unwrap_or(job.default_schedule.to_string());
+        let schedule = match Schedule::parse(&raw) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                println!("[scheduler] {}: {}", env_name, e);
+                std::process::exit(1);
+            }
+        };
+
+        println!("[scheduler] {} runs on {:?}", job.name, raw);
+        STATUS.lock().unwrap().insert(
+            job.name,
+            JobStatus {
+                name: job.name,
+                schedule: raw,
+                running: false,
+                next_run: None,
+                last_started: None,
+                last_finished: None,
+                last_duration_ms: None,
+                last_rows_affected: None,
+                last_error: None,
+                consecutive_failures: 0,
+            },
+        );
+
+        let trigger = Arc::new(Notify::new());
+        TRIGGERS.lock().unwrap().insert(job.name, trigger.clone());
+        tokio::spawn(job_loop(job, schedule, trigger));
+    }
+
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(telemetry::handler))
+        .route("/jobs/:name/run", post(trigger_handler));
+
+    let port: u16 = std::env::var("PORT")
+        .unwrap_or("8998".to_string())
+        .parse()
+        .unwrap();
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    println!("[scheduler] Listening on http://{}", addr);
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+}
+
+/// Always 200 while the scheduler is up. `status` is `degraded` if any job's last run failed.
+async fn healthz() -> impl IntoResponse {
+    let jobs: Vec<JobStatus> = STATUS.lock().unwrap().values().cloned().collect();
+    let failing = jobs.iter().any(|job| job.last_error.is_some());
+
+    Json(json!({
+        "status": if failing { "degraded" } else { "ok" },
+        "jobs": jobs,
+    }))
+}
+
+/// POST /jobs/:name/run
+async fn trigger_handler(Path(name): Path<String>, headers: HeaderMap) -> impl IntoResponse {
+    // Anyone who can reach the port could run prune or migrate otherwise
+    if ADMIN_TOKEN.is_empty() {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "ADMIN_TOKEN not set, running jobs over HTTP is off" })),
+        );
+    }
+
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value == format!("Bearer {}", *ADMIN_TOKEN))
+        .unwrap_or(false);
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "unauthorized" })),
+        );
+    }
+
+    let trigger = match TRIGGERS.lock().unwrap().get(name.as_str()) {
+        Some(trigger) => trigger.clone(),
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": format!("no job named {}", name) })),
+            )
+        }
+    };
+
+    let running = STATUS
+        .lock()
+        .unwrap()
+        .get(name.as_str())
+        .map(|status| status.running)
+        .unwrap_or(false);
+    if running {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("{} is already running", name) })),
+        );
+    }
+
+    trigger.notify_one();
+    (StatusCode::ACCEPTED, Json(json!({ "triggered": name })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn every(raw: &str) -> chrono::Duration {
+        match Schedule::parse(raw) {
+            Ok(Schedule::Every(interval)) => interval,
+            Ok(Schedule::Cron(_)) => panic!("{:?} parsed as cron", raw),
+            Err(e) => panic!("{:?} didn't parse: {}", raw, e),
+        }
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(every("30s"), chrono::Duration::seconds(30));
+        assert_eq!(every("45"), chrono::Duration::seconds(45));
+        assert_eq!(every("5m"), chrono::Duration::minutes(5));
+        assert_eq!(every(" 1h "), chrono::Duration::hours(1));
+    }
+
+    #[test]
+    fn rejects_bad_intervals() {
+        for raw in ["", "m", "0s", "5d", "5 m", "-5m", "1.5h"] {
+            assert!(
+                Schedule::parse(raw).is_err(),
+                "{:?} should be an error",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn parses_cron_with_seconds() {
+        let schedule = Schedule::parse("0 */5 * * * *").unwrap();
+        assert!(matches!(schedule, Schedule::Cron(_)));
+
+        let time = Utc.with_ymd_and_hms(2024, 1, 31, 13, 2, 30).unwrap();
+        assert_eq!(
+            schedule.next_after(time),
+            Utc.with_ymd_and_hms(2024, 1, 31, 13, 5, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_cron() {
+        let e = Schedule::parse("0 */5 * * *  nope").err().unwrap();
+        assert!(e.starts_with("bad cron expression"), "{}", e);
+        assert!(Schedule::parse("61 * * * * *").is_err());
+    }
+
+    #[test]
+    fn intervals_run_after_their_interval() {
+        let time = Utc.with_ymd_and_hms(2024, 1, 31, 13, 2, 30).unwrap();
+        assert_eq!(
+            Schedule::parse("5m").unwrap().next_after(time),
+            time + chrono::Duration::minutes(5)
+        );
+    }
+}
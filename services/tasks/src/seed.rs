@@ -0,0 +1,299 @@
+use crate::PG;
+use catalog::{for_each_class, for_each_vehicle, for_each_world, for_each_zone, translators};
+use chrono::{Duration, Utc};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
+use sqlx::{Postgres, QueryBuilder};
+
+/// Rows per INSERT.
+const BATCH_SIZE: usize = 1000;
+
+/// Seeded players and vehicles are last seen within this many seconds, so they all count as active.
+const ACTIVE_SECONDS: i64 = 10 * 60;
+
+macro_rules! world_list {
+    ($($field:ident: $id:literal, $name:literal, $platform:ident, $environment:ident, $description:tt;)*) => {
+        &[$((stringify!($field), $id, stringify!($platform)),)*]
+    };
+}
+
+macro_rules! zone_list {
+    ($($field:ident: $id:literal, $name:literal;)*) => {
+        &[$((stringify!($field), $id),)*]
+    };
+}
+
+macro_rules! name_list {
+    ($($name:ident)*) => {
+        &[$(stringify!($name),)*]
+    };
+}
+
+static WORLDS: &[(&str, i32, &str)] = for_each_world!(world_list);
+static ZONES: &[(&str, i32)] = for_each_zone!(zone_list);
+static CLASSES: &[&str] = for_each_class!(name_list);
+static VEHICLES: &[&str] = for_each_vehicle!(name_list);
+
+/// Roughly how often each class shows up in a real fight.
+fn class_weight(class_name: &str) -> u32 {
+    match class_name {
+        "heavy_assault" => 30,
+        "combat_medic" => 20,
+        "max" => 5,
+        _ => 15,
+    }
+}
+
+/// Vehicles only one faction can pull. Everything else is shared.
+fn vehicle_faction(vehicle_name: &str) -> Option<i32> {
+    match vehicle_name {
+        "magrider" | "scythe" => Some(1),
+        "vanguard" | "reaver" => Some(2),
+        "prowler" | "mosquito" => Some(3),
+        "chimera" | "dervish" | "javelin" => Some(4),
+        _ => None,
+    }
+}
+
+/// Event names as the websocket writes them, and how common each is.
+static EVENTS: &[(&str, u32)] = &[
+    ("Death", 60),
+    ("VehicleDestroy", 10),
+    ("GainExperience_1", 15),
+    ("GainExperience_201", 3),
+    ("GainExperience_233", 7),
+    ("GainExperience_674", 5),
+];
+
+fn usage() -> ! {
+    println!("Usage: tasks seed [options]");
+    println!("Options:");
+    println!("  --players <n>     Active players, default 2000");
+    println!("  --vehicles <n>    Active vehicles, default 500, at most one per player");
+    println!("  --events <n>      Analytics events, default 20000");
+    println!("  --hours <n>       How far back events go, default 24");
+    println!("  --worlds <list>   Comma-separated world names or IDs, default all");
+    println!("  --zones <list>    Comma-separated zone names or IDs, default all");
+    println!("  --seed <n>        Random seed, so runs can be repeated");
+    std::process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+    println!("[seed] ERR => {}", message);
+    std::process::exit(2);
+}
+
+struct SeedArgs {
+    players: usize,
+    vehicles: usize,
+    events: usize,
+    hours: i64,
+    worlds: Vec<(i32, &'static str)>,
+    zones: Vec<i32>,
+    seed: Option<u64>,
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| fail(format!("{} {:?} isn't a number", flag, value)))
+}
+
+/// Picks entries by name or ID from a comma-separated list.
+fn pick<T: Copy>(flag: &str, list: &str, entries: &[T], name_id: fn(&T) -> (&str, i32)) -> Vec<T> {
+    list.split(',')
+        .map(|wanted| {
+            let wanted = wanted.trim().to_lowercase();
+            *entries
+                .iter()
+                .find(|entry| {
+                    let (name, id) = name_id(entry);
+                    name == wanted || id.to_string() == wanted
+                })
+                .unwrap_or_else(|| fail(format!("{} {:?} isn't a known name or ID", flag, wanted)))
+        })
+        .collect()
+}
+
+fn parse_args(args: &[String]) -> SeedArgs {
+    let mut seed_args = SeedArgs {
+        players: 2000,
+        vehicles: 500,
+        events: 20000,
+        hours: 24,
+        worlds: WORLDS
+            .iter()
+            .map(|(_, id, platform)| (*id, *platform))
+            .collect(),
+        zones: ZONES.iter().map(|(_, id)| *id).collect(),
+        seed: None,
+    };
+
+    let mut rest = args.iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--players" => seed_args.players = number(flag, value),
+            "--vehicles" => seed_args.vehicles = number(flag, value),
+            "--events" => seed_args.events = number(flag, value),
+            "--hours" => seed_args.hours = number(flag, value),
+            "--seed" => seed_args.seed = Some(number(flag, value)),
+            "--worlds" => {
+                seed_args.worlds = pick(flag, value, WORLDS, |(name, id, _)| (name, *id))
+                    .into_iter()
+                    .map(|(_, id, platform)| (id, platform))
+                    .collect()
+            }
+            "--zones" => {
+                seed_args.zones = pick(flag, value, ZONES, |(name, id)| (name, *id))
+                    .into_iter()
+                    .map(|(_, id)| id)
+                    .collect()
+            }
+            _ => usage(),
+        }
+    }
+
+    seed_args.vehicles = seed_args.vehicles.min(seed_args.players);
+    seed_args
+}
+
+/// A seeded player. IDs start with `seed-`, and re-running replaces the same characters.
+struct Player {
+    character_id: String,
+    seconds_ago: i64,
+    world_id: i32,
+    platform: &'static str,
+    faction_id: i32,
+    zone_id: i32,
+    class_name: &'static str,
+}
+
+/// Fills `players`, `vehicles`, and `analytics` with made up but plausible data, for working
+/// on the API and dashboards without a live feed.
+pub async fn cmd_seed(args: &[String]) {
+    let args = parse_args(args);
+    let pool = PG.get().await;
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // NS is a much smaller share of the population than the three empires
+    let factions = WeightedIndex::new([30, 30, 30, 10]).unwrap();
+    let classes = WeightedIndex::new(CLASSES.iter().map(|name| class_weight(name))).unwrap();
+
+    let players: Vec<Player> = (0..args.players)
+        .map(|index| {
+            let (world_id, platform) = *args.worlds.choose(&mut rng).unwrap();
+            Player {
+                character_id: format!("seed-{}", index),
+                seconds_ago: rng.gen_range(0..ACTIVE_SECONDS),
+                world_id,
+                platform: if platform == "Ps4" { "ps4" } else { "pc" },
+                faction_id: factions.sample(&mut rng) as i32 + 1,
+                zone_id: *args.zones.choose(&mut rng).unwrap(),
+                class_name: CLASSES[classes.sample(&mut rng)],
+            }
+        })
+        .collect();
+
+    println!("Seeding {} players...", players.len());
+    for batch in players.chunks(BATCH_SIZE) {
+        let mut insert: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO players (character_id, last_updated, world_id, faction_id, zone_id, class_name, platform) ",
+        );
+        insert.push_values(batch, |mut row, player| {
+            row.push_bind(player.character_id.as_str())
+                .push_bind(Utc::now() - Duration::seconds(player.seconds_ago))
+                .push_bind(player.world_id)
+                .push_bind(player.faction_id)
+                .push_bind(player.zone_id)
+                .push_bind(player.class_name)
+                .push_bind(player.platform);
+        });
+        insert.push(
+            " ON CONFLICT (character_id) DO UPDATE SET
+            last_updated = EXCLUDED.last_updated, world_id = EXCLUDED.world_id,
+            faction_id = EXCLUDED.faction_id, zone_id = EXCLUDED.zone_id,
+            class_name = EXCLUDED.class_name, platform = EXCLUDED.platform;",
+        );
+        insert.build().execute(pool).await.unwrap();
+    }
+
+    println!("Seeding {} vehicles...", args.vehicles);
+    let drivers: Vec<(&Player, &str)> = players[..args.vehicles]
+        .iter()
+        .map(|player| {
+            let usable: Vec<&str> = VEHICLES
+                .iter()
+                .copied()
+                .filter(|name| vehicle_faction(name).map_or(true, |f| f == player.faction_id))
+                .collect();
+            (player, *usable.choose(&mut rng).unwrap())
+        })
+        .collect();
+    for batch in drivers.chunks(BATCH_SIZE) {
+        let mut insert: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO vehicles (character_id, last_updated, world_id, faction_id, zone_id, vehicle_name, category, domain, platform) ",
+        );
+        insert.push_values(batch, |mut row, (player, vehicle_name)| {
+            row.push_bind(player.character_id.as_str())
+                .push_bind(Utc::now() - Duration::seconds(player.seconds_ago))
+                .push_bind(player.world_id)
+                .push_bind(player.faction_id)
+                .push_bind(player.zone_id)
+                .push_bind(*vehicle_name)
+                .push_bind(translators::vehicle_to_category(vehicle_name))
+                .push_bind(translators::vehicle_to_domain(vehicle_name))
+                .push_bind(player.platform);
+        });
+        insert.push(
+            " ON CONFLICT (character_id) DO UPDATE SET
+            last_updated = EXCLUDED.last_updated, world_id = EXCLUDED.world_id,
+            faction_id = EXCLUDED.faction_id, zone_id = EXCLUDED.zone_id,
+            vehicle_name = EXCLUDED.vehicle_name, category = EXCLUDED.category,
+            domain = EXCLUDED.domain, platform = EXCLUDED.platform;",
+        );
+        insert.build().execute(pool).await.unwrap();
+    }
+
+    println!(
+        "Seeding {} analytics events over {} hours...",
+        args.events, args.hours
+    );
+    let events = WeightedIndex::new(EVENTS.iter().map(|(_, weight)| weight)).unwrap();
+    let spread = (args.hours * 3600).max(1);
+    for start in (0..args.events).step_by(BATCH_SIZE) {
+        let batch: Vec<(i64, i32, &str, &str)> = (start..args.events.min(start + BATCH_SIZE))
+            .map(|_| {
+                let (world_id, platform) = *args.worlds.choose(&mut rng).unwrap();
+                (
+                    rng.gen_range(0..spread),
+                    world_id,
+                    EVENTS[events.sample(&mut rng)].0,
+                    if platform == "Ps4" { "ps4" } else { "pc" },
+                )
+            })
+            .collect();
+
+        let mut insert: QueryBuilder<Postgres> =
+            QueryBuilder::new("INSERT INTO analytics (time, world_id, event_name, platform) ");
+        insert.push_values(
+            batch,
+            |mut row, (seconds_ago, world_id, event_name, platform)| {
+                row.push_bind(Utc::now() - Duration::seconds(seconds_ago))
+                    .push_bind(world_id)
+                    .push_bind(event_name)
+                    .push_bind(platform);
+            },
+        );
+        insert.build().execute(pool).await.unwrap();
+    }
+
+    println!("Done!");
+}
@@ -0,0 +1,65 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    gather, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+lazy_static! {
+    // jobs
+    pub static ref JOB_RUNS: IntCounterVec = register_int_counter_vec!("saerro_tasks_job_runs", "Job runs", &[
+        "job", "result"
+    ]).unwrap();
+    pub static ref JOB_FAILURES: IntCounterVec = register_int_counter_vec!("saerro_tasks_job_failures", "Job runs that failed", &[
+        "job"
+    ]).unwrap();
+    pub static ref JOB_ROWS: IntCounterVec = register_int_counter_vec!("saerro_tasks_job_rows_affected", "Rows affected by a job, added up over every run", &[
+        "job"
+    ]).unwrap();
+    pub static ref JOB_LAST_ROWS: IntGaugeVec = register_int_gauge_vec!("saerro_tasks_job_last_rows_affected", "Rows affected by a job's last successful run", &[
+        "job"
+    ]).unwrap();
+    pub static ref JOB_LAST_RUN: IntGaugeVec = register_int_gauge_vec!("saerro_tasks_job_last_run_time", "Unix time a job last finished", &[
+        "job"
+    ]).unwrap();
+    pub static ref JOB_LAST_SUCCESS: IntGaugeVec = register_int_gauge_vec!("saerro_tasks_job_last_success_time", "Unix time a job last finished without failing", &[
+        "job"
+    ]).unwrap();
+    pub static ref JOB_DURATION: HistogramVec = register_histogram_vec!(
+        "saerro_tasks_job_duration_seconds",
+        "How long a job run took",
+        &["job"],
+        vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0]
+    ).unwrap();
+}
+
+pub async fn handler() -> String {
+    let encoder = TextEncoder::new();
+    let mut buffer = String::new();
+
+    let metrics = gather();
+    encoder
+        .encode_utf8(&metrics, &mut buffer)
+        .expect("prometheus metrics failed to render");
+
+    buffer
+}
+
+pub fn job_succeeded(job: &str, seconds: f64, rows: u64) {
+    let now = chrono::Utc::now().timestamp();
+
+    JOB_RUNS.with_label_values(&[job, "success"]).inc();
+    JOB_ROWS.with_label_values(&[job]).inc_by(rows);
+    JOB_LAST_ROWS.with_label_values(&[job]).set(rows as i64);
+    JOB_LAST_RUN.with_label_values(&[job]).set(now);
+    JOB_LAST_SUCCESS.with_label_values(&[job]).set(now);
+    JOB_DURATION.with_label_values(&[job]).observe(seconds);
+}
+
+pub fn job_failed(job: &str, seconds: f64) {
+    JOB_RUNS.with_label_values(&[job, "failure"]).inc();
+    JOB_FAILURES.with_label_values(&[job]).inc();
+    JOB_LAST_RUN
+        .with_label_values(&[job])
+        .set(chrono::Utc::now().timestamp());
+    JOB_DURATION.with_label_values(&[job]).observe(seconds);
+}
@@ -0,0 +1,500 @@
+use crate::{telemetry, PG};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use sqlx::{query, Row};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    /// Base URL of a Census-compatible API, like `https://census.daybreakgames.com/s:example`.
+    /// Enrichment is disabled when this isn't set.
+    static ref CENSUS_ADDR: String = std::env::var("CENSUS_ADDR").unwrap_or_default();
+    /// Minimum time between two Census requests.
+    static ref REQUEST_INTERVAL: Duration = Duration::from_millis(
+        std::env::var("CENSUS_RATE_LIMIT_MS")
+            .unwrap_or("1000".to_string())
+            .parse()
+            .unwrap()
+    );
+    /// How long a cached character is trusted before it's looked up again.
+    static ref CACHE_TTL: Duration = Duration::from_secs(
+        60 * 60 * std::env::var("CENSUS_CACHE_HOURS")
+            .unwrap_or("24".to_string())
+            .parse::<u64>()
+            .unwrap()
+    );
+    /// Characters queued within the cache TTL, so they aren't queued again while their lookup is
+    /// pending or cached. Failed lookups are forgotten, so they're retried the next time the
+    /// character is seen.
+    static ref RECENTLY_QUEUED: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Character IDs paired with the Census namespace they live in.
+static QUEUE: OnceLock<UnboundedSender<(String, &'static str)>> = OnceLock::new();
+
+/// Census caps how many IDs we should put in one request.
+const BATCH_SIZE: usize = 50;
+
+#[derive(Deserialize, Debug)]
+struct CharacterName {
+    first: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Outfit {
+    outfit_id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    alias: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Character {
+    character_id: String,
+    name: Option<CharacterName>,
+    outfit: Option<Outfit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CharacterResponse {
+    #[serde(default)]
+    character_list: Vec<Character>,
+}
+
+/// Starts the enrichment worker if CENSUS_ADDR is configured.
+pub fn start() {
+    if CENSUS_ADDR.is_empty() {
+        println!("[enrichment] CENSUS_ADDR not set, outfit enrichment disabled");
+        return;
+    }
+
+    let (tx, rx) = unbounded();
+    if QUEUE.set(tx).is_ok() {
+        println!("[enrichment] Resolving characters via {}", *CENSUS_ADDR);
+        tokio::spawn(worker(rx));
+    }
+}
+
+/// Queues a character to have their name and outfit resolved from the given Census namespace.
+/// Characters already queued within the cache TTL are skipped.
+pub fn enqueue(character_id: &str, census_namespace: &'static str) {
+    let queue = match QUEUE.get() {
+        Some(queue) => queue,
+        None => return,
+    };
+
+    {
+        let mut recently_queued = RECENTLY_QUEUED.lock().unwrap();
+        if let Some(queued_at) = recently_queued.get(character_id) {
+            if queued_at.elapsed() < *CACHE_TTL {
+                return;
+            }
+        }
+
+        // Keep this from growing forever
+        if recently_queued.len() > 100_000 {
+            recently_queued.retain(|_, queued_at| queued_at.elapsed() < *CACHE_TTL);
+        }
+
+        recently_queued.insert(character_id.to_string(), Instant::now());
+    }
+
+    queue
+        .unbounded_send((character_id.to_string(), census_namespace))
+        .ok();
+}
+
+/// Lets characters whose lookup failed be queued again.
+fn forget(character_ids: &[String]) {
+    let mut recently_queued = RECENTLY_QUEUED.lock().unwrap();
+    for character_id in character_ids {
+        recently_queued.remove(character_id);
+    }
+}
+
+async fn worker(mut rx: UnboundedReceiver<(String, &'static str)>) {
+    let mut interval = tokio::time::interval(*REQUEST_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    while let Some(batch) = next_batch(&mut rx).await {
+        for (census_namespace, character_ids) in batch {
+            resolve(&CENSUS_ADDR, census_namespace, character_ids, &mut interval).await;
+        }
+    }
+}
+
+/// Waits for a queued character, then takes whatever else is already queued, up to
+/// `BATCH_SIZE` in all, grouped by namespace. None once the queue is closed.
+async fn next_batch(
+    rx: &mut UnboundedReceiver<(String, &'static str)>,
+) -> Option<HashMap<&'static str, Vec<String>>> {
+    let mut batch = vec![rx.next().await?];
+    while batch.len() < BATCH_SIZE {
+        match rx.try_next() {
+            Ok(Some(item)) => batch.push(item),
+            _ => break,
+        }
+    }
+
+    let mut by_namespace: HashMap<&'static str, Vec<String>> = HashMap::new();
+    for (character_id, census_namespace) in batch {
+        by_namespace
+            .entry(census_namespace)
+            .or_default()
+            .push(character_id);
+    }
+
+    Some(by_namespace)
+}
+
+/// Looks up the characters that aren't cached yet from `census_addr` and stores them.
+/// `interval` is only waited on when Census is actually asked.
+async fn resolve(
+    census_addr: &str,
+    census_namespace: &str,
+    character_ids: Vec<String>,
+    interval: &mut tokio::time::Interval,
+) {
+    let missing = match uncached(&character_ids).await {
+        Ok(missing) => missing,
+        Err(e) => {
+            println!("[enrichment/uncached] ERR => {:?}", e);
+            forget(&character_ids);
+            return;
+        }
+    };
+
+    if missing.is_empty() {
+        return;
+    }
+
+    interval.tick().await;
+    match fetch_characters(census_addr, census_namespace, &missing).await {
+        Ok(characters) => {
+            telemetry::census_request("ok");
+            if let Err(e) = store_characters(&missing, characters).await {
+                println!("[enrichment/store_characters] ERR => {:?}", e);
+                forget(&missing);
+            }
+        }
+        Err(e) => {
+            telemetry::census_request("error");
+            println!("[enrichment/fetch_characters] ERR => {:?}", e);
+            forget(&missing);
+        }
+    }
+}
+
+/// Filters out characters that are already cached and fresh.
+async fn uncached(character_ids: &[String]) -> Result<Vec<String>, sqlx::Error> {
+    let pool = PG.get().await;
+
+    let timer = telemetry::db_read("characters", "uncached");
+    let cached: HashSet<String> = query(
+        "SELECT character_id FROM characters WHERE character_id = ANY($1) AND updated_at > now() - make_interval(secs => $2);",
+    )
+    .bind(character_ids)
+    .bind(CACHE_TTL.as_secs_f64())
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.get(0))
+    .collect();
+    timer.observe_duration();
+
+    let mut missing: Vec<String> = character_ids
+        .iter()
+        .filter(|character_id| !cached.contains(*character_id))
+        .cloned()
+        .collect();
+    missing.sort();
+    missing.dedup();
+
+    Ok(missing)
+}
+
+async fn fetch_characters(
+    census_addr: &str,
+    census_namespace: &str,
+    character_ids: &[String],
+) -> Result<Vec<Character>, reqwest::Error> {
+    let url = format!(
+        "{}/get/{}/character/?character_id={}&c:show=character_id,name.first&c:resolve=outfit(outfit_id,name,alias)",
+        census_addr.trim_end_matches('/'),
+        census_namespace,
+        character_ids.join(","),
+    );
+
+    let response: CharacterResponse = reqwest::get(url).await?.error_for_status()?.json().await?;
+
+    Ok(response.character_list)
+}
+
+async fn store_characters(
+    requested: &[String],
+    characters: Vec<Character>,
+) -> Result<(), sqlx::Error> {
+    let pool = PG.get().await;
+
+    for character in characters.iter() {
+        if let Some(outfit) = &character.outfit {
+            let timer = telemetry::db_write("outfits", "store_characters");
+            query(
+                "INSERT INTO outfits (outfit_id, name, alias, updated_at) VALUES ($1, $2, $3, now())
+                ON CONFLICT (outfit_id) DO UPDATE SET
+                    name = EXCLUDED.name,
+                    alias = EXCLUDED.alias,
+                    updated_at = EXCLUDED.updated_at;",
+            )
+            .bind(&outfit.outfit_id)
+            .bind(&outfit.name)
+            .bind(&outfit.alias)
+            .execute(pool)
+            .await?;
+            timer.observe_duration();
+        }
+    }
+
+    // Characters Census didn't return are cached too, so we don't keep asking for them.
+    let found: HashMap<&str, &Character> = characters
+        .iter()
+        .map(|character| (character.character_id.as_str(), character))
+        .collect();
+
+    for character_id in requested {
+        let character = found.get(character_id.as_str());
+        let name = character
+            .and_then(|character| character.name.as_ref())
+            .map(|name| name.first.clone());
+        let outfit_id = character
+            .and_then(|character| character.outfit.as_ref())
+            .map(|outfit| outfit.outfit_id.clone())
+            .filter(|outfit_id| outfit_id != "0");
+
+        let timer = telemetry::db_write("characters", "store_characters");
+        query(
+            "INSERT INTO characters (character_id, name, outfit_id, updated_at) VALUES ($1, $2, $3, now())
+            ON CONFLICT (character_id) DO UPDATE SET
+                name = EXCLUDED.name,
+                outfit_id = EXCLUDED.outfit_id,
+                updated_at = EXCLUDED.updated_at;",
+        )
+        .bind(character_id)
+        .bind(name)
+        .bind(outfit_id)
+        .execute(pool)
+        .await?;
+        timer.observe_duration();
+    }
+
+    let timer = telemetry::db_write("players", "store_characters");
+    query(
+        "UPDATE players SET outfit_id = characters.outfit_id FROM characters
+        WHERE players.character_id = characters.character_id AND characters.character_id = ANY($1);",
+    )
+    .bind(requested)
+    .execute(pool)
+    .await?;
+    timer.observe_duration();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::Uri, Json, Router};
+    use std::sync::Arc;
+
+    const WITH_OUTFIT: &str = "9990000000000000001";
+    const WITHOUT_OUTFIT: &str = "9990000000000000002";
+    const NOT_FOUND: &str = "9990000000000000003";
+
+    /// Serves a canned Census character response on a random local port, and keeps every
+    /// request URI. Returns the base URL to use as the Census address.
+    async fn mock_census() -> (String, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let app = Router::new().fallback(move |uri: Uri| {
+            let seen = seen.clone();
+            async move {
+                seen.lock().unwrap().push(uri.to_string());
+                Json(serde_json::json!({
+                    "character_list": [
+                        {
+                            "character_id": WITH_OUTFIT,
+                            "name": { "first": "Outfitted" },
+                            "outfit": { "outfit_id": "9990000000000000100", "name": "Test Outfit", "alias": "TST" }
+                        },
+                        {
+                            "character_id": WITHOUT_OUTFIT,
+                            "name": { "first": "Loner" }
+                        }
+                    ],
+                    "returned": 2
+                }))
+            }
+        });
+
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (format!("http://{}/s:test/", addr), requests)
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn fetch_characters_asks_the_namespace_for_every_id() {
+        let (census_addr, requests) = mock_census().await;
+
+        let characters = fetch_characters(
+            &census_addr,
+            "ps2ps4us:v2",
+            &ids(&[WITH_OUTFIT, WITHOUT_OUTFIT, NOT_FOUND]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![format!(
+                "/s:test/get/ps2ps4us:v2/character/?character_id={},{},{}&c:show=character_id,name.first&c:resolve=outfit(outfit_id,name,alias)",
+                WITH_OUTFIT, WITHOUT_OUTFIT, NOT_FOUND
+            )]
+        );
+        assert_eq!(characters.len(), 2);
+        assert_eq!(
+            characters[0]
+                .outfit
+                .as_ref()
+                .map(|outfit| outfit.alias.as_str()),
+            Some("TST")
+        );
+        assert!(characters[1].outfit.is_none());
+    }
+
+    #[tokio::test]
+    async fn next_batch_caps_size_and_groups_by_namespace() {
+        let (tx, mut rx) = unbounded();
+        for i in 0..120 {
+            let census_namespace = if i % 2 == 0 { "ps2:v2" } else { "ps2ps4eu:v2" };
+            tx.unbounded_send((i.to_string(), census_namespace))
+                .unwrap();
+        }
+
+        let mut sizes = Vec::new();
+        for _ in 0..3 {
+            let batch = next_batch(&mut rx).await.unwrap();
+            assert_eq!(batch.len(), 2);
+            sizes.push(batch.values().map(|ids| ids.len()).sum::<usize>());
+        }
+        assert_eq!(sizes, vec![BATCH_SIZE, BATCH_SIZE, 20]);
+
+        drop(tx);
+        assert!(next_batch(&mut rx).await.is_none());
+    }
+
+    /// Needs Postgres with migrations applied, at DATABASE_URL. Every test using it runs from
+    /// here, since PG's connections belong to the runtime of the first test that opens them.
+    #[tokio::test]
+    async fn with_postgres() {
+        if std::env::var("DATABASE_URL").is_err() {
+            println!("DATABASE_URL isn't set, skipping");
+            return;
+        }
+
+        resolve_stores_characters_and_caches_them().await;
+        failed_lookups_can_be_queued_again().await;
+    }
+
+    async fn resolve_stores_characters_and_caches_them() {
+        let pool = PG.get().await;
+        let requested = ids(&[WITH_OUTFIT, WITHOUT_OUTFIT, NOT_FOUND]);
+        let clean_up = || async {
+            query("DELETE FROM characters WHERE character_id = ANY($1);")
+                .bind(&requested)
+                .execute(pool)
+                .await
+                .unwrap();
+            query("DELETE FROM outfits WHERE outfit_id = '9990000000000000100';")
+                .execute(pool)
+                .await
+                .unwrap();
+        };
+        clean_up().await;
+
+        let (census_addr, requests) = mock_census().await;
+        let mut interval = tokio::time::interval(Duration::from_millis(1));
+        resolve(&census_addr, "ps2:v2", requested.clone(), &mut interval).await;
+
+        let rows: Vec<(String, Option<String>, Option<String>)> = query(
+            "SELECT character_id, name, outfit_id FROM characters WHERE character_id = ANY($1) ORDER BY character_id;",
+        )
+        .bind(&requested)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    WITH_OUTFIT.to_string(),
+                    Some("Outfitted".to_string()),
+                    Some("9990000000000000100".to_string())
+                ),
+                (WITHOUT_OUTFIT.to_string(), Some("Loner".to_string()), None),
+                // Cached as missing, so it isn't asked for again
+                (NOT_FOUND.to_string(), None, None),
+            ]
+        );
+
+        let alias: String =
+            query("SELECT alias FROM outfits WHERE outfit_id = '9990000000000000100';")
+                .fetch_one(pool)
+                .await
+                .unwrap()
+                .get(0);
+        assert_eq!(alias, "TST");
+
+        // Everything is cached now, so Census isn't asked again
+        resolve(&census_addr, "ps2:v2", requested.clone(), &mut interval).await;
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert!(uncached(&requested).await.unwrap().is_empty());
+
+        clean_up().await;
+    }
+
+    async fn failed_lookups_can_be_queued_again() {
+        let requested = ids(&["9990000000000000004"]);
+        RECENTLY_QUEUED
+            .lock()
+            .unwrap()
+            .insert(requested[0].clone(), Instant::now());
+
+        // Nothing listens on port 1, so the Census request fails
+        let mut interval = tokio::time::interval(Duration::from_millis(1));
+        resolve(
+            "http://127.0.0.1:1/s:test",
+            "ps2:v2",
+            requested.clone(),
+            &mut interval,
+        )
+        .await;
+
+        assert!(!RECENTLY_QUEUED.lock().unwrap().contains_key(&requested[0]));
+    }
+}
@@ -0,0 +1,118 @@
+use catalog::for_each_world;
+
+/// A PlanetSide 2 environment, which has its own event stream and Census namespace.
+#[derive(Debug)]
+pub struct Environment {
+    /// Our name for it, as used in the ENVIRONMENTS variable.
+    pub name: &'static str,
+    /// The `environment` parameter on the ESS URL.
+    pub ess_name: &'static str,
+    /// The Census namespace for character lookups.
+    pub census_namespace: &'static str,
+    /// What we store in the `platform` column.
+    pub platform: &'static str,
+}
+
+macro_rules! world_environments {
+    ($($field:ident: $id:literal, $name:literal, $platform:ident, $environment:ident, $description:tt;)*) => {
+        /// Every world in the catalog, with the name of the environment it's on.
+        static WORLDS: &[(&str, i32)] = &[$((stringify!($environment), $id),)*];
+    };
+}
+
+for_each_world!(world_environments);
+
+pub static ENVIRONMENTS: [Environment; 3] = [
+    Environment {
+        name: "pc",
+        ess_name: "ps2",
+        census_namespace: "ps2:v2",
+        platform: "pc",
+    },
+    Environment {
+        name: "ps4us",
+        ess_name: "ps2ps4us",
+        census_namespace: "ps2ps4us:v2",
+        platform: "ps4",
+    },
+    Environment {
+        name: "ps4eu",
+        ess_name: "ps2ps4eu",
+        census_namespace: "ps2ps4eu:v2",
+        platform: "ps4",
+    },
+];
+
+/// Environments we should connect to, from ENVIRONMENTS (comma separated, default `all`).
+pub fn configured() -> Vec<&'static Environment> {
+    let environments_raw = std::env::var("ENVIRONMENTS").unwrap_or("all".to_string());
+    let names: Vec<&str> = environments_raw
+        .split(',')
+        .map(|name| name.trim())
+        .collect();
+
+    ENVIRONMENTS
+        .iter()
+        .filter(|environment| names.contains(&"all") || names.contains(&environment.name))
+        .collect()
+}
+
+impl Environment {
+    /// IDs of the worlds on this environment.
+    pub fn worlds(&self) -> Vec<i32> {
+        WORLDS
+            .iter()
+            .filter(|(environment, _)| *environment == self.name)
+            .map(|(_, world)| *world)
+            .collect()
+    }
+
+    /// WS_ADDR with its `environment` parameter pointed at this environment.
+    pub fn url(&self, addr: &str) -> url::Url {
+        let mut url = url::Url::parse(addr).unwrap();
+
+        // Rebuilt by hand so the rest of the query (like `service-id=s:...`) stays untouched
+        let mut pairs = vec![format!("environment={}", self.ess_name)];
+        pairs.extend(
+            url.query()
+                .unwrap_or_default()
+                .split('&')
+                .filter(|pair| !pair.is_empty() && !pair.starts_with("environment="))
+                .map(|pair| pair.to_string()),
+        );
+        url.set_query(Some(pairs.join("&").as_str()));
+
+        url
+    }
+
+    /// Worlds from WORLDS that belong to this environment, or None if there's nothing to subscribe to.
+    pub fn subscribed_worlds(&self, worlds_raw: &str) -> Option<Vec<String>> {
+        let worlds: Vec<&str> = worlds_raw.split(',').map(|world| world.trim()).collect();
+        if worlds.contains(&"all") {
+            return Some(
+                self.worlds()
+                    .iter()
+                    .map(|world| world.to_string())
+                    .collect(),
+            );
+        }
+
+        let known = self.worlds();
+        let worlds: Vec<String> = worlds
+            .into_iter()
+            .filter(|world| {
+                world
+                    .parse::<i32>()
+                    .map(|world| known.contains(&world))
+                    .unwrap_or(false)
+            })
+            .map(|world| world.to_string())
+            .collect();
+
+        if worlds.is_empty() {
+            None
+        } else {
+            Some(worlds)
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use async_once::AsyncOnce;
-use axum::{routing::get, Json, Router};
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use environments::Environment;
 use futures::{pin_mut, FutureExt};
 use futures_util::StreamExt;
 use lazy_static::lazy_static;
@@ -11,8 +15,12 @@ use std::{env, net::SocketAddr};
 use tokio::task::JoinSet;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
-mod translators;
+mod enrichment;
+mod environments;
+mod sessions;
+mod status;
 mod telemetry;
+mod translator_tables;
 
 lazy_static! {
     static ref WS_ADDR: String = env::var("WS_ADDR").unwrap_or_default();
@@ -23,10 +31,11 @@ lazy_static! {
     });
 }
 
-async fn send_init(tx: futures::channel::mpsc::UnboundedSender<Message>) {
-    let worlds_raw = env::var("WORLDS").unwrap_or("all".to_string());
-    let worlds: Vec<&str> = worlds_raw.split(',').collect();
-
+async fn send_init(
+    environment: &'static Environment,
+    worlds: Vec<String>,
+    tx: futures::channel::mpsc::UnboundedSender<Message>,
+) {
     let experience_ids = vec![
         2, 3, 4, 5, 6, 7, 34, 51, 53, 55, 57, 86, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98, 99,
         100, 129, 130, 131, 132, 133, 134, 135, 136, 137, 138, 139, 140, 141, 142, 201, 233, 293,
@@ -53,12 +62,13 @@ async fn send_init(tx: futures::channel::mpsc::UnboundedSender<Message>) {
     tx.unbounded_send(Message::text(setup_msg.to_string()))
         .unwrap();
 
-    println!("[ws] Sent setup message");
-    println!("[ws/setup] {}", setup_msg.to_string())
+    println!("[ws/{}] Sent setup message", environment.name);
+    println!("[ws/{}/setup] {}", environment.name, setup_msg)
 }
 
 #[derive(Clone)]
 struct PopEvent {
+    environment: &'static Environment,
     world_id: i32,
     team_id: i32,
     character_id: String,
@@ -69,19 +79,32 @@ struct PopEvent {
 
 #[derive(Debug)]
 struct AnalyticsEvent {
+    environment: &'static Environment,
     world_id: i32,
     event_name: String,
 }
 
+#[derive(Debug)]
+struct VehicleDestroyEvent {
+    environment: &'static Environment,
+    world_id: i32,
+    zone_id: i32,
+    faction_id: i32,
+    vehicle_id: String,
+    attacker_faction_id: i32,
+    attacker_vehicle_id: String,
+}
+
 async fn get_team_id(character_id: String) -> Result<i32, sqlx::Error> {
     let pool = PG.get().await;
 
-    telemetry::db_read("players", "get_team_id");
+    let timer = telemetry::db_read("players", "get_team_id");
     let team_id: i32 = query("SELECT faction_id FROM players WHERE character_id = $1 LIMIT 1;")
         .bind(character_id.clone())
         .fetch_one(pool)
         .await?
         .get(0);
+    timer.observe_duration();
 
     if team_id == 0 {
         return Err(sqlx::Error::RowNotFound);
@@ -95,6 +118,7 @@ async fn track_pop(pop_event: PopEvent) {
     let pool = PG.get().await;
 
     let PopEvent {
+        environment,
         world_id,
         team_id,
         character_id,
@@ -103,24 +127,25 @@ async fn track_pop(pop_event: PopEvent) {
         vehicle_id,
     } = pop_event;
 
-    let class_name = translators::loadout_to_class(loadout_id.as_str());
-    let vehicle_name = if vehicle_id == "" {
+    let class_name = translator_tables::loadout_to_class(loadout_id.as_str());
+    let vehicle_name = if vehicle_id.is_empty() {
         "unknown".to_string()
     } else {
-        translators::vehicle_to_name(vehicle_id.as_str())
+        translator_tables::vehicle_to_name(vehicle_id.as_str())
     };
 
-    telemetry::db_write("players", "track_pop");
+    let timer = telemetry::db_write("players", "track_pop");
     query(
         "
-        INSERT INTO players (last_updated, character_id, world_id, faction_id, zone_id, class_name) 
-        VALUES (now(), $1, $2, $3, $4, $5) 
+        INSERT INTO players (last_updated, character_id, world_id, faction_id, zone_id, class_name, outfit_id, platform) 
+        VALUES (now(), $1, $2, $3, $4, $5, (SELECT outfit_id FROM characters WHERE character_id = $1), $6) 
         ON CONFLICT (character_id) DO UPDATE SET 
             last_updated = EXCLUDED.last_updated,
             world_id = EXCLUDED.world_id,
             faction_id = EXCLUDED.faction_id,
             zone_id = EXCLUDED.zone_id,
-            class_name = EXCLUDED.class_name
+            class_name = EXCLUDED.class_name,
+            platform = EXCLUDED.platform
     ;",
     )
     .bind(character_id.clone())
@@ -128,29 +153,50 @@ async fn track_pop(pop_event: PopEvent) {
     .bind(team_id)
     .bind(zone_id)
     .bind(class_name)
+    .bind(environment.platform)
     .execute(pool)
     .await
     .unwrap();
+    timer.observe_duration();
+
+    sessions::track_session(
+        character_id.clone(),
+        world_id,
+        team_id,
+        environment.platform,
+    )
+    .await;
+    enrichment::enqueue(&character_id, environment.census_namespace);
 
     if vehicle_name != "unknown" {
-        telemetry::db_write("vehicles", "track_pop");
-        query("INSERT INTO vehicles (last_updated, character_id, world_id, faction_id, zone_id, vehicle_name) 
-        VALUES (now(), $1, $2, $3, $4, $5) 
+        let category = translator_tables::vehicle_to_category(vehicle_name.as_str());
+        let domain = translator_tables::vehicle_to_domain(vehicle_name.as_str());
+
+        let timer = telemetry::db_write("vehicles", "track_pop");
+        query("INSERT INTO vehicles (last_updated, character_id, world_id, faction_id, zone_id, vehicle_name, category, domain, platform) 
+        VALUES (now(), $1, $2, $3, $4, $5, $6, $7, $8) 
         ON CONFLICT (character_id) DO UPDATE SET
             last_updated = EXCLUDED.last_updated,
             world_id = EXCLUDED.world_id,
             faction_id = EXCLUDED.faction_id,
             zone_id = EXCLUDED.zone_id,
-            vehicle_name = EXCLUDED.vehicle_name
+            vehicle_name = EXCLUDED.vehicle_name,
+            category = EXCLUDED.category,
+            domain = EXCLUDED.domain,
+            platform = EXCLUDED.platform
     ;")
         .bind(character_id)
         .bind(world_id)
         .bind(team_id)
         .bind(zone_id)
         .bind(vehicle_name)
+        .bind(category)
+        .bind(domain)
+        .bind(environment.platform)
         .execute(pool)
         .await
         .unwrap();
+        timer.observe_duration();
     }
 }
 
@@ -159,17 +205,23 @@ async fn track_analytics(analytics_event: AnalyticsEvent) {
     let pool = PG.get().await;
 
     let AnalyticsEvent {
+        environment,
         world_id,
         event_name,
     } = analytics_event;
 
-    telemetry::db_write("analytics", "track_analytics");
-    match query("INSERT INTO analytics (time, world_id, event_name) VALUES (now(), $1, $2);")
-        .bind(world_id)
-        .bind(event_name)
-        .execute(pool)
-        .await
-    {
+    let timer = telemetry::db_write("analytics", "track_analytics");
+    let result = query(
+        "INSERT INTO analytics (time, world_id, event_name, platform) VALUES (now(), $1, $2, $3);",
+    )
+    .bind(world_id)
+    .bind(event_name)
+    .bind(environment.platform)
+    .execute(pool)
+    .await;
+    timer.observe_duration();
+
+    match result {
         Ok(_) => {}
         Err(e) => {
             println!("[ws/track_analytics] ERR => {:?}", e);
@@ -177,55 +229,121 @@ async fn track_analytics(analytics_event: AnalyticsEvent) {
     }
 }
 
-async fn process_death_event(event: &Event) {
+async fn track_vehicle_destroy(destroy_event: VehicleDestroyEvent) {
+    let pool = PG.get().await;
+
+    let VehicleDestroyEvent {
+        environment,
+        world_id,
+        zone_id,
+        faction_id,
+        vehicle_id,
+        attacker_faction_id,
+        attacker_vehicle_id,
+    } = destroy_event;
+
+    let vehicle_name = translator_tables::vehicle_to_name(vehicle_id.as_str());
+    if vehicle_name == "unknown" {
+        return;
+    }
+
+    // No attacker vehicle means the kill came from someone on foot
+    let attacker_vehicle_name = if attacker_vehicle_id.is_empty() || attacker_vehicle_id == "0" {
+        "infantry".to_string()
+    } else {
+        translator_tables::vehicle_to_name(attacker_vehicle_id.as_str())
+    };
+
+    let timer = telemetry::db_write("vehicle_destroys", "track_vehicle_destroy");
+    let result = query(
+        "
+        INSERT INTO vehicle_destroys (time, world_id, zone_id, faction_id, vehicle_name, attacker_faction_id, attacker_vehicle_name, platform)
+        VALUES (now(), $1, $2, $3, $4, $5, $6, $7);",
+    )
+    .bind(world_id)
+    .bind(zone_id)
+    .bind(faction_id)
+    .bind(vehicle_name)
+    .bind(attacker_faction_id)
+    .bind(attacker_vehicle_name)
+    .bind(environment.platform)
+    .execute(pool)
+    .await;
+    timer.observe_duration();
+
+    match result {
+        Ok(_) => {}
+        Err(e) => {
+            println!("[ws/track_vehicle_destroy] ERR => {:?}", e);
+        }
+    }
+}
+
+async fn process_death_event(environment: &'static Environment, event: &Event) {
     let mut set = JoinSet::new();
     // println!("[ws/process_event] EVENT: {:?}", event);
 
     set.spawn(track_analytics(AnalyticsEvent {
-        world_id: event.world_id.clone(),
+        environment,
+        world_id: event.world_id,
         event_name: event.event_name.clone(),
     }));
 
-    if event.character_id != "" && event.character_id != "0" {
+    if event.event_name == "VehicleDestroy" {
+        set.spawn(track_vehicle_destroy(VehicleDestroyEvent {
+            environment,
+            world_id: event.world_id,
+            zone_id: event.zone_id,
+            faction_id: if event.faction_id != 0 {
+                event.faction_id
+            } else {
+                event.team_id
+            },
+            vehicle_id: event.vehicle_id.clone(),
+            attacker_faction_id: event.attacker_team_id,
+            attacker_vehicle_id: event.attacker_vehicle_id.clone(),
+        }));
+    }
+
+    if !event.character_id.is_empty() && event.character_id != "0" {
         set.spawn(track_pop(PopEvent {
-            world_id: event.world_id.clone(),
-            team_id: event.team_id.clone(),
+            environment,
+            world_id: event.world_id,
+            team_id: event.team_id,
             character_id: event.character_id.clone(),
-            zone_id: event.zone_id.clone(),
+            zone_id: event.zone_id,
             loadout_id: event.loadout_id.clone(),
             vehicle_id: event.vehicle_id.clone(),
         }));
     }
 
-    if event.attacker_character_id != ""
+    if !event.attacker_character_id.is_empty()
         && event.attacker_character_id != "0"
-        && (event.attacker_team_id != 0 || event.attacker_team_id != 0)
+        && event.attacker_team_id != 0
     {
         set.spawn(track_pop(PopEvent {
-            world_id: event.world_id.clone(),
-            team_id: event.attacker_team_id.clone(),
+            environment,
+            world_id: event.world_id,
+            team_id: event.attacker_team_id,
             character_id: event.attacker_character_id.clone(),
-            zone_id: event.zone_id.clone(),
+            zone_id: event.zone_id,
             loadout_id: event.attacker_loadout_id.clone(),
             vehicle_id: event.attacker_vehicle_id.clone(),
         }));
     }
 
-    while let Some(_) = set.join_next().await {}
+    while set.join_next().await.is_some() {}
 }
 
-async fn process_exp_event(event: &Event) {
-    telemetry::experience_event(&event.world_id, &event.experience_id); 
+async fn process_exp_event(environment: &'static Environment, event: &Event) {
+    telemetry::experience_event(&event.world_id, &event.experience_id);
     let mut set = JoinSet::new();
     // println!("[ws/process_event] EVENT: {:?}", event);
 
     set.spawn(track_analytics(AnalyticsEvent {
-        world_id: event.world_id.clone(),
-        event_name: format!(
-            "{}_{}",
-            event.event_name.clone(),
-            event.experience_id.clone()
-        ),
+        environment,
+        world_id: event.world_id,
+        event_name: format!("{}_{}", event.event_name.clone(), event.experience_id),
     }));
 
     // Vehicle EXP events
@@ -237,15 +355,16 @@ async fn process_exp_event(event: &Event) {
     };
 
     set.spawn(track_pop(PopEvent {
-        world_id: event.world_id.clone(),
-        team_id: event.team_id.clone(),
+        environment,
+        world_id: event.world_id,
+        team_id: event.team_id,
         character_id: event.character_id.clone(),
-        zone_id: event.zone_id.clone(),
+        zone_id: event.zone_id,
         loadout_id: event.loadout_id.clone(),
         vehicle_id: vehicle_id.clone(),
     }));
 
-    while let Some(_) = set.join_next().await {}
+    while set.join_next().await.is_some() {}
 }
 #[derive(Deserialize, Debug, Clone, Default)]
 struct Event {
@@ -259,6 +378,8 @@ struct Event {
     attacker_team_id: i32,
     #[serde(default, deserialize_with = "deserialize_number_from_string")]
     team_id: i32,
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    faction_id: i32,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     zone_id: i32,
 
@@ -276,6 +397,10 @@ struct Event {
 
     #[serde(default, deserialize_with = "deserialize_number_from_string")]
     experience_id: i32,
+
+    /// Unix time the event happened in game
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    timestamp: i64,
     // #[serde(default)]
     // other_id: String,
 }
@@ -286,17 +411,13 @@ struct Payload {
 }
 
 async fn healthz() {
-    let app = Router::new().route(
-        "/healthz",
-        get(|| async {
-            Json(json!({
-                "status": "ok",
-            }))
-        }),
-    ).route(
-        "/metrics",
-        get(telemetry::handler)
-    );
+    let app = Router::new()
+        .route("/healthz", get(status::handler))
+        .route("/metrics", get(telemetry::handler))
+        .route(
+            "/translators/reload",
+            post(translator_tables::reload_handler),
+        );
 
     let port: u16 = std::env::var("PORT")
         .unwrap_or("8999".to_string())
@@ -312,74 +433,167 @@ async fn healthz() {
         .unwrap();
 }
 
-#[tokio::main]
-async fn main() {
-    let addr: String = WS_ADDR.to_string();
-    if addr == "" {
-        println!("WS_ADDR not set");
+async fn process_message(environment: &'static Environment, body: &str) {
+    let mut data: Payload = match serde_json::from_str(body) {
+        Ok(data) => data,
+        Err(_e) => {
+            // println!("Error: {}; body: {}", e, body.clone());
+            telemetry::event_dropped(&0, "", "decoding failure");
+            return;
+        }
+    };
+
+    if data.payload.event_name.is_empty() {
+        telemetry::event_dropped(
+            &data.payload.world_id,
+            &data.payload.event_name,
+            "not event",
+        );
         return;
     }
-    let url = url::Url::parse(&addr).unwrap();
 
-    println!("[ws] Connecting to {}", url);
+    telemetry::event(&data.payload.world_id, &data.payload.event_name);
+    telemetry::event_lag(&data.payload.world_id, data.payload.timestamp);
+    status::world_event(data.payload.world_id);
 
-    let (tx, rx) = futures::channel::mpsc::unbounded();
-    let (ws_stream, _) = connect_async(url).await.expect("Failed to connect");
-    let (write, read) = ws_stream.split();
+    if data.payload.event_name == "Death" || data.payload.event_name == "VehicleDestroy" {
+        process_death_event(environment, &data.payload).await;
+        return;
+    }
 
-    let fused_writer = rx.map(Ok).forward(write).fuse();
-    let fused_reader = read
-        .for_each(|msg| async {
-            let body = &msg.unwrap().to_string();
-
-            let mut data: Payload = match serde_json::from_str(body) {
-                Ok(data) => data,
-                Err(_e) => {
-                    // println!("Error: {}; body: {}", e, body.clone());
-                    telemetry::event_dropped(&0, &"".to_string(), "decoding failure");
-                    return;
+    if data.payload.event_name == "GainExperience" {
+        if data.payload.team_id == 0 {
+            match get_team_id(data.payload.character_id.clone()).await {
+                Ok(team_id) => {
+                    data.payload.team_id = team_id;
+                }
+                Err(_) => {
+                    telemetry::event_dropped(
+                        &data.payload.world_id,
+                        &data.payload.event_name,
+                        "team_id missing",
+                    );
                 }
-            };
-
-            if data.payload.event_name == "" {
-                telemetry::event_dropped(&data.payload.world_id, &data.payload.event_name, "not event");
-                return;
             }
+        }
+        process_exp_event(environment, &data.payload).await;
+        return;
+    }
 
-            telemetry::event(&data.payload.world_id, &data.payload.event_name);
+    telemetry::event_dropped(
+        &data.payload.world_id,
+        &data.payload.event_name,
+        "unprocessable",
+    );
+}
 
-            if data.payload.event_name == "Death" || data.payload.event_name == "VehicleDestroy" {
-                process_death_event(&data.payload).await;
-                return;
-            }
+/// Connects to one environment's event stream and processes it until the connection drops.
+async fn connect_environment(
+    environment: &'static Environment,
+    worlds: Vec<String>,
+) -> Result<(), String> {
+    let url = environment.url(&WS_ADDR);
 
-            if data.payload.event_name == "GainExperience" {
-                if data.payload.team_id == 0 {
-                    match get_team_id(data.payload.character_id.clone()).await {
-                        Ok(team_id) => {
-                            data.payload.team_id = team_id;
-                        }
-                        Err(_) => {
-                            telemetry::event_dropped(&data.payload.world_id, &data.payload.event_name, "team_id missing");
+    println!("[ws/{}] Connecting to {}", environment.name, url);
+    status::connecting(environment, &url);
+
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    let (ws_stream, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+    let (write, read) = ws_stream.split();
+    status::connected(environment);
+
+    // Reading and processing are split up so a processing backlog shows up as queue depth
+    let (queue_tx, mut queue_rx) = futures::channel::mpsc::unbounded::<String>();
+
+    let connection = async move {
+        let fused_writer = rx.map(Ok).forward(write).fuse();
+        let fused_reader = read
+            .for_each(move |msg| {
+                let queue_tx = queue_tx.clone();
+                async move {
+                    match msg {
+                        Ok(msg) => {
+                            let body = msg.to_string();
+                            if body.starts_with("{\"subscription\"") {
+                                status::subscribed(environment);
+                            }
+
+                            status::message_queued(environment);
+                            queue_tx.unbounded_send(body).ok();
                         }
+                        Err(e) => println!("[ws/{}] ERR => {:?}", environment.name, e),
                     }
                 }
-                process_exp_event(&data.payload).await;
-                return;
-            }
+            })
+            .fuse();
 
-            telemetry::event_dropped(&data.payload.world_id, &data.payload.event_name, "unprocessable");
-        })
-        .fuse();
+        pin_mut!(fused_writer, fused_reader);
+        futures::select! {
+            _ = fused_reader => {}
+            _ = fused_writer => {}
+        }
+        // The reader owns the queue's sender, so once it's dropped here the processor
+        // finishes what's already queued and stops, instead of those messages being lost
+    };
+    let processor = async move {
+        while let Some(body) = queue_rx.next().await {
+            process_message(environment, &body).await;
+            status::message_processed(environment);
+        }
+    };
 
-    pin_mut!(fused_writer, fused_reader);
+    send_init(environment, worlds, tx.clone()).await;
+    futures::join!(connection, processor);
 
-    let init = tokio::spawn(send_init(tx.clone()));
-    let mut healthz = tokio::spawn(healthz()).fuse();
-    futures::select! {
-        _ = fused_reader => {}
-        _ = fused_writer => {}
-        _ = healthz => {}
+    Err("connection closed".to_string())
+}
+
+/// Keeps one environment connected, reconnecting with backoff.
+/// Each environment runs on its own so one failing stream doesn't take the others down.
+async fn run_environment(environment: &'static Environment, worlds: Vec<String>) {
+    let mut backoff = 5;
+    loop {
+        let started = std::time::Instant::now();
+        if let Err(e) = connect_environment(environment, worlds.clone()).await {
+            println!("[ws/{}] Disconnected: {}", environment.name, e);
+            status::disconnected(environment, &e);
+        }
+
+        // A connection that stayed up for a while resets the backoff
+        if started.elapsed().as_secs() > 60 {
+            backoff = 5;
+        }
+
+        println!("[ws/{}] Reconnecting in {}s", environment.name, backoff);
+        tokio::time::sleep(tokio::time::Duration::from_secs(backoff)).await;
+        backoff = (backoff * 2).min(60);
     }
-    init.await.unwrap();
+}
+
+#[tokio::main]
+async fn main() {
+    let addr: String = WS_ADDR.to_string();
+    if addr.is_empty() {
+        println!("WS_ADDR not set");
+        return;
+    }
+
+    translator_tables::start().await;
+
+    let worlds_raw = env::var("WORLDS").unwrap_or("all".to_string());
+    for environment in environments::configured() {
+        match environment.subscribed_worlds(&worlds_raw) {
+            Some(worlds) => {
+                tokio::spawn(run_environment(environment, worlds));
+            }
+            None => println!(
+                "[ws/{}] No worlds to subscribe to, skipping",
+                environment.name
+            ),
+        }
+    }
+
+    tokio::spawn(sessions::session_sweeper());
+    enrichment::start();
+    healthz().await;
 }
@@ -0,0 +1,186 @@
+use crate::{telemetry, PG};
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use sqlx::query;
+use std::{collections::HashMap, sync::Mutex};
+
+lazy_static! {
+    /// How long a character can go without activity before their session is closed.
+    static ref SESSION_GAP: Duration = Duration::minutes(
+        std::env::var("SESSION_GAP_MINUTES")
+            .unwrap_or("15".to_string())
+            .parse()
+            .unwrap()
+    );
+    static ref OPEN_SESSIONS: Mutex<HashMap<String, OpenSession>> = Mutex::new(HashMap::new());
+}
+
+/// Only write last_seen back to Postgres this often while a session is being extended.
+/// Closing a session always writes the final value.
+const EXTEND_INTERVAL_SECONDS: i64 = 60;
+
+#[derive(Clone, Debug)]
+struct OpenSession {
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    last_written: DateTime<Utc>,
+}
+
+/// What track_session still has to write to Postgres once OPEN_SESSIONS is unlocked.
+enum Write {
+    Nothing,
+    /// Extend the session that started at `first_seen`.
+    Extend(DateTime<Utc>),
+    /// Open a new session, closing the previous one if there was one.
+    Open(Option<OpenSession>),
+}
+
+/// Opens a session for the character, or extends their current one if it's still within the gap.
+pub async fn track_session(character_id: String, world_id: i32, faction_id: i32, platform: &str) {
+    let now = Utc::now();
+
+    // Looked up and updated under one lock, so two events for the same character can't both
+    // decide to open a session
+    let write = {
+        let mut sessions = OPEN_SESSIONS.lock().unwrap();
+
+        match sessions.get_mut(&character_id) {
+            Some(session) if now - session.last_seen < *SESSION_GAP => {
+                session.last_seen = now;
+                if now - session.last_written > Duration::seconds(EXTEND_INTERVAL_SECONDS) {
+                    session.last_written = now;
+                    Write::Extend(session.first_seen)
+                } else {
+                    Write::Nothing
+                }
+            }
+            _ => Write::Open(sessions.insert(
+                character_id.clone(),
+                OpenSession {
+                    first_seen: now,
+                    last_seen: now,
+                    last_written: now,
+                },
+            )),
+        }
+    };
+
+    match write {
+        Write::Nothing => {}
+        Write::Extend(first_seen) => {
+            write_last_seen(&character_id, first_seen, now, "extend_session").await;
+        }
+        Write::Open(previous) => {
+            if let Some(session) = previous {
+                write_last_seen(
+                    &character_id,
+                    session.first_seen,
+                    session.last_seen,
+                    "close_session",
+                )
+                .await;
+            }
+
+            open_session(&character_id, world_id, faction_id, platform, now).await;
+        }
+    }
+}
+
+/// Closes every session that has gone quiet for longer than the gap, flushing its final last_seen.
+pub async fn close_stale_sessions() {
+    let now = Utc::now();
+
+    let stale: Vec<(String, OpenSession)> = {
+        let mut sessions = OPEN_SESSIONS.lock().unwrap();
+        let stale_ids: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| now - session.last_seen >= *SESSION_GAP)
+            .map(|(character_id, _)| character_id.clone())
+            .collect();
+
+        stale_ids
+            .into_iter()
+            .filter_map(|character_id| {
+                sessions
+                    .remove(&character_id)
+                    .map(|session| (character_id, session))
+            })
+            .collect()
+    };
+
+    for (character_id, session) in stale {
+        if session.last_seen > session.last_written {
+            write_last_seen(
+                &character_id,
+                session.first_seen,
+                session.last_seen,
+                "close_session",
+            )
+            .await;
+        }
+    }
+}
+
+/// Runs close_stale_sessions once a minute, forever.
+pub async fn session_sweeper() {
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+        close_stale_sessions().await;
+    }
+}
+
+async fn open_session(
+    character_id: &str,
+    world_id: i32,
+    faction_id: i32,
+    platform: &str,
+    now: DateTime<Utc>,
+) {
+    let pool = PG.get().await;
+
+    let timer = telemetry::db_write("sessions", "open_session");
+    let result = query(
+        "INSERT INTO sessions (character_id, world_id, faction_id, platform, first_seen, last_seen) VALUES ($1, $2, $3, $4, $5, $5);",
+    )
+    .bind(character_id)
+    .bind(world_id)
+    .bind(faction_id)
+    .bind(platform)
+    .bind(now)
+    .execute(pool)
+    .await;
+    timer.observe_duration();
+
+    match result {
+        Ok(_) => {}
+        Err(e) => {
+            println!("[ws/open_session] ERR => {:?}", e);
+        }
+    }
+}
+
+async fn write_last_seen(
+    character_id: &str,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    op: &str,
+) {
+    let pool = PG.get().await;
+
+    let timer = telemetry::db_write("sessions", op);
+    let result =
+        query("UPDATE sessions SET last_seen = $3 WHERE character_id = $1 AND first_seen = $2;")
+            .bind(character_id)
+            .bind(first_seen)
+            .bind(last_seen)
+            .execute(pool)
+            .await;
+    timer.observe_duration();
+
+    match result {
+        Ok(_) => {}
+        Err(e) => {
+            println!("[ws/{}] ERR => {:?}", op, e);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::environments::Environment;
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::json;
+use std::{collections::BTreeMap, sync::Mutex};
+
+lazy_static! {
+    static ref ENVIRONMENTS: Mutex<BTreeMap<&'static str, EnvironmentStatus>> =
+        Mutex::new(BTreeMap::new());
+    static ref WORLDS: Mutex<BTreeMap<i32, DateTime<Utc>>> = Mutex::new(BTreeMap::new());
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EnvironmentStatus {
+    environment: &'static str,
+    state: ConnectionState,
+    /// The ESS URL, with the service ID redacted.
+    upstream: String,
+    connected_since: Option<DateTime<Utc>>,
+    last_message: Option<DateTime<Utc>>,
+    /// Did ESS acknowledge our subscription on this connection?
+    subscribed: bool,
+    /// Messages received but not processed yet.
+    queue_depth: i64,
+    last_error: Option<String>,
+}
+
+fn update(environment: &'static Environment, f: impl FnOnce(&mut EnvironmentStatus)) {
+    let mut environments = ENVIRONMENTS.lock().unwrap();
+    let status = environments
+        .entry(environment.name)
+        .or_insert_with(|| EnvironmentStatus {
+            environment: environment.name,
+            state: ConnectionState::Disconnected,
+            upstream: "".to_string(),
+            connected_since: None,
+            last_message: None,
+            subscribed: false,
+            queue_depth: 0,
+            last_error: None,
+        });
+    f(status);
+}
+
+fn redact(url: &url::Url) -> String {
+    let mut url = url.clone();
+    let query = url.query().map(|query| {
+        query
+            .split('&')
+            .map(|pair| {
+                if pair.starts_with("service-id=") {
+                    "service-id=REDACTED"
+                } else {
+                    pair
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join("&")
+    });
+    url.set_query(query.as_deref());
+    url.to_string()
+}
+
+pub fn connecting(environment: &'static Environment, url: &url::Url) {
+    update(environment, |status| {
+        status.state = ConnectionState::Connecting;
+        status.upstream = redact(url);
+        status.subscribed = false;
+    });
+}
+
+pub fn connected(environment: &'static Environment) {
+    update(environment, |status| {
+        status.state = ConnectionState::Connected;
+        status.connected_since = Some(Utc::now());
+        status.last_error = None;
+    });
+}
+
+pub fn disconnected(environment: &'static Environment, error: &str) {
+    update(environment, |status| {
+        status.state = ConnectionState::Disconnected;
+        status.connected_since = None;
+        status.subscribed = false;
+        status.queue_depth = 0;
+        status.last_error = Some(error.to_string());
+    });
+}
+
+pub fn subscribed(environment: &'static Environment) {
+    update(environment, |status| status.subscribed = true);
+}
+
+/// A message arrived and was queued for processing.
+pub fn message_queued(environment: &'static Environment) {
+    update(environment, |status| {
+        status.last_message = Some(Utc::now());
+        status.queue_depth += 1;
+    });
+}
+
+pub fn message_processed(environment: &'static Environment) {
+    update(environment, |status| {
+        status.queue_depth = (status.queue_depth - 1).max(0);
+    });
+}
+
+pub fn world_event(world_id: i32) {
+    WORLDS.lock().unwrap().insert(world_id, Utc::now());
+}
+
+pub async fn handler() -> impl IntoResponse {
+    let environments: Vec<EnvironmentStatus> =
+        ENVIRONMENTS.lock().unwrap().values().cloned().collect();
+    let worlds = WORLDS.lock().unwrap().clone();
+
+    let healthy = environments
+        .iter()
+        .filter(|status| status.state == ConnectionState::Connected && status.subscribed)
+        .count();
+    let (code, status) = if environments.is_empty() || healthy == 0 {
+        (StatusCode::SERVICE_UNAVAILABLE, "down")
+    } else if healthy < environments.len() {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+
+    (
+        code,
+        Json(json!({
+            "status": status,
+            "environments": environments,
+            "worlds": worlds
+                .iter()
+                .map(|(world_id, last_event)| json!({ "id": world_id, "last_event": last_event }))
+                .collect::<Vec<_>>(),
+            "queue_depth": environments.iter().map(|status| status.queue_depth).sum::<i64>(),
+        })),
+    )
+}
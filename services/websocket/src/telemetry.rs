@@ -1,75 +1,120 @@
 use lazy_static::lazy_static;
-use prometheus::{ 
-  IntGaugeVec,
-  register_int_gauge_vec,
-  TextEncoder,
-  gather
+use prometheus::{
+    gather, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
 };
 
 lazy_static! {
-  // incoming events
-  pub static ref EVENTS: IntGaugeVec = register_int_gauge_vec!("saerro_ws_events_count", "Events processed", &[
-    "world_id", "event_name"
-  ]).unwrap();
-  pub static ref EVENTS_DROPPED: IntGaugeVec = register_int_gauge_vec!("saerro_ws_events_dropped_count", "Events dropped", &[
-    "world_id", "event_name", "reason"
-  ]).unwrap();
-
-  pub static ref EXPERIENCE_EVENTS: IntGaugeVec = register_int_gauge_vec!("saerro_ws_experience_events_count", "Experience Events processed by Exp ID", &[
-    "world_id", "experience_id"
-  ]).unwrap();
-
-  // database stuff
-  pub static ref DB_WRITES: IntGaugeVec = register_int_gauge_vec!("saerro_ws_db_writes", "Writes to Postgres", &[
-    "table", "op"
-  ]).unwrap();
-  pub static ref DB_READS: IntGaugeVec = register_int_gauge_vec!("saerro_ws_db_reads", "Reads from Postgres", &[
-    "table", "op"
-  ]).unwrap();
-  // static ref DB_WTIME: HistogramVec = register_histogram_vec!("saerro_ws_db_write_time", &[
-  //   "table", "op"
-  // ]).unwrap();
-  // static ref DB_RTIME: HistogramVec = register_histogram_vec!("saerro_ws_db_read_time", &[
-  //   "table", "op"
-  // ]).unwrap();
+    // incoming events
+    pub static ref EVENTS: IntCounterVec = register_int_counter_vec!("saerro_ws_events_count", "Events processed", &[
+        "world_id", "event_name"
+    ]).unwrap();
+    pub static ref EVENTS_DROPPED: IntCounterVec = register_int_counter_vec!("saerro_ws_events_dropped_count", "Events dropped", &[
+        "world_id", "event_name", "reason"
+    ]).unwrap();
+
+    pub static ref EXPERIENCE_EVENTS: IntCounterVec = register_int_counter_vec!("saerro_ws_experience_events_count", "Experience Events processed by Exp ID", &[
+        "world_id", "experience_id"
+    ]).unwrap();
+
+    // ingest pipeline
+    pub static ref INGEST_LAG: HistogramVec = register_histogram_vec!(
+        "saerro_ws_ingest_lag_seconds",
+        "Time between an event happening in game and us processing it",
+        &["world_id"],
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]
+    ).unwrap();
+    pub static ref LAST_EVENT_TIME: IntGaugeVec = register_int_gauge_vec!("saerro_ws_last_event_time", "Unix time the last processed event happened in game, per world", &[
+        "world_id"
+    ]).unwrap();
+
+    // translators
+    pub static ref TRANSLATOR_MISSES: IntCounterVec = register_int_counter_vec!("saerro_ws_translator_misses", "IDs missing from the translator tables", &[
+        "table"
+    ]).unwrap();
+
+    // census
+    pub static ref CENSUS_REQUESTS: IntCounterVec = register_int_counter_vec!("saerro_ws_census_requests", "Requests to Census for character enrichment", &[
+        "status"
+    ]).unwrap();
+
+    // database stuff
+    pub static ref DB_WRITES: IntCounterVec = register_int_counter_vec!("saerro_ws_db_writes", "Writes to Postgres", &[
+        "table", "op"
+    ]).unwrap();
+    pub static ref DB_READS: IntCounterVec = register_int_counter_vec!("saerro_ws_db_reads", "Reads from Postgres", &[
+        "table", "op"
+    ]).unwrap();
+    pub static ref DB_WTIME: HistogramVec = register_histogram_vec!("saerro_ws_db_write_time", "Time spent writing to Postgres", &[
+        "table", "op"
+    ]).unwrap();
+    pub static ref DB_RTIME: HistogramVec = register_histogram_vec!("saerro_ws_db_read_time", "Time spent reading from Postgres", &[
+        "table", "op"
+    ]).unwrap();
 }
 
 pub async fn handler() -> String {
-  let encoder = TextEncoder::new();
-  let mut buffer = String::new();
+    let encoder = TextEncoder::new();
+    let mut buffer = String::new();
 
-  let metrics = gather();
-  encoder.encode_utf8(&metrics, &mut buffer).expect("prometheus metrics failed to render");
+    let metrics = gather();
+    encoder
+        .encode_utf8(&metrics, &mut buffer)
+        .expect("prometheus metrics failed to render");
 
-  buffer
+    buffer
 }
 
-pub fn event(world_id: &i32, event_name: &String) {
-  EVENTS.with_label_values(&[
-    &world_id.to_string(),
-    &event_name,
-  ]).inc();
+pub fn event(world_id: &i32, event_name: &str) {
+    EVENTS
+        .with_label_values(&[&world_id.to_string(), event_name])
+        .inc();
 }
 
-pub fn event_dropped(world_id: &i32, event_name: &String, reason: &str) {
-  EVENTS_DROPPED.with_label_values(&[
-    &world_id.to_string(),
-    &event_name,
-    reason,
-  ]).inc();
+pub fn event_dropped(world_id: &i32, event_name: &str, reason: &str) {
+    EVENTS_DROPPED
+        .with_label_values(&[&world_id.to_string(), event_name, reason])
+        .inc();
+}
+
+/// Records when the last event happened and how far behind the game we are, from the event's own unix timestamp.
+pub fn event_lag(world_id: &i32, timestamp: i64) {
+    let now = chrono::Utc::now();
+
+    // Some events come without a timestamp
+    if timestamp > 0 {
+        LAST_EVENT_TIME
+            .with_label_values(&[&world_id.to_string()])
+            .set(timestamp);
+        let lag = now.timestamp_millis() as f64 / 1000.0 - timestamp as f64;
+        INGEST_LAG
+            .with_label_values(&[&world_id.to_string()])
+            .observe(lag.max(0.0));
+    }
 }
 
 pub fn experience_event(world_id: &i32, experience_id: &i32) {
-  EXPERIENCE_EVENTS.with_label_values(&[
-    &world_id.to_string(),
-    &experience_id.to_string(),
-  ]).inc();
+    EXPERIENCE_EVENTS
+        .with_label_values(&[&world_id.to_string(), &experience_id.to_string()])
+        .inc();
+}
+
+pub fn translator_miss(table: &str) {
+    TRANSLATOR_MISSES.with_label_values(&[table]).inc();
 }
 
-pub fn db_write(table: &str, op: &str) {
-  DB_WRITES.with_label_values(&[table, op]).inc();
+pub fn census_request(status: &str) {
+    CENSUS_REQUESTS.with_label_values(&[status]).inc();
 }
 
-pub fn db_read(table: &str, op: &str) {
-  DB_READS.with_label_values(&[table, op]).inc();
-}
\ No newline at end of file
+/// Counts the write and starts timing it. Call `observe_duration` on the timer once the query is done.
+pub fn db_write(table: &str, op: &str) -> HistogramTimer {
+    DB_WRITES.with_label_values(&[table, op]).inc();
+    DB_WTIME.with_label_values(&[table, op]).start_timer()
+}
+
+/// Counts the read and starts timing it. Call `observe_duration` on the timer once the query is done.
+pub fn db_read(table: &str, op: &str) -> HistogramTimer {
+    DB_READS.with_label_values(&[table, op]).inc();
+    DB_RTIME.with_label_values(&[table, op]).start_timer()
+}
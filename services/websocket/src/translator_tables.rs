@@ -0,0 +1,221 @@
+use crate::{telemetry, PG};
+use axum::{
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use catalog::translators;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{query, Row};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, RwLock},
+};
+
+lazy_static! {
+    /// Where translator tables are loaded from: a path to a JSON file, or `postgres` for the
+    /// translators table. When unset, only the compiled tables in translators.rs are used.
+    static ref SOURCE: String = std::env::var("TRANSLATORS").unwrap_or_default();
+    /// The reload endpoint needs `Authorization: Bearer <ADMIN_TOKEN>`, and is off when it's unset.
+    static ref ADMIN_TOKEN: String = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+    static ref TABLES: RwLock<Tables> = RwLock::new(Tables::default());
+    static ref LOGGED_MISSES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Runtime additions to the compiled tables. Entries here win over compiled ones.
+/// Categories and domains are by vehicle name, the rest by ID.
+///
+/// As JSON: `{ "vehicles": { "2143": "chimera" }, "loadouts": { "46": "max" },
+/// "categories": { "chimera": "mbt" }, "domains": { "chimera": "ground" } }`
+#[derive(Deserialize, Default, Debug)]
+struct Tables {
+    #[serde(default)]
+    vehicles: HashMap<String, String>,
+    #[serde(default)]
+    loadouts: HashMap<String, String>,
+    #[serde(default)]
+    categories: HashMap<String, String>,
+    #[serde(default)]
+    domains: HashMap<String, String>,
+}
+
+pub fn vehicle_to_name(vehicle_id: &str) -> String {
+    if let Some(name) = TABLES.read().unwrap().vehicles.get(vehicle_id) {
+        return name.clone();
+    }
+
+    let name = translators::vehicle_to_name(vehicle_id);
+    if name == "unknown" {
+        miss("vehicle", vehicle_id);
+    }
+
+    name
+}
+
+pub fn vehicle_to_category(vehicle_name: &str) -> String {
+    match TABLES.read().unwrap().categories.get(vehicle_name) {
+        Some(category) => category.clone(),
+        None => translators::vehicle_to_category(vehicle_name),
+    }
+}
+
+pub fn vehicle_to_domain(vehicle_name: &str) -> String {
+    match TABLES.read().unwrap().domains.get(vehicle_name) {
+        Some(domain) => domain.clone(),
+        None => translators::vehicle_to_domain(vehicle_name),
+    }
+}
+
+pub fn loadout_to_class(loadout_id: &str) -> String {
+    if let Some(class) = TABLES.read().unwrap().loadouts.get(loadout_id) {
+        return class.clone();
+    }
+
+    let class = translators::loadout_to_class(loadout_id);
+    if class == "unknown" {
+        miss("loadout", loadout_id);
+    }
+
+    class
+}
+
+/// Counts an ID we couldn't translate, and logs it the first time we see it.
+fn miss(table: &str, id: &str) {
+    // Empty and zero IDs mean "none" (on foot, no loadout given), not a missing entry
+    if id.is_empty() || id == "0" {
+        return;
+    }
+
+    telemetry::translator_miss(table);
+
+    let key = format!("{}/{}", table, id);
+    if LOGGED_MISSES.lock().unwrap().insert(key) {
+        println!("[translators] Unknown {} ID {}", table, id);
+    }
+}
+
+/// Loads the tables from TRANSLATORS, replacing what was loaded before.
+/// On failure the previous tables stay in place.
+pub async fn load() -> Result<usize, String> {
+    let tables = match SOURCE.as_str() {
+        "" => return Ok(0),
+        "postgres" => load_postgres().await.map_err(|e| e.to_string())?,
+        path => load_file(path)?,
+    };
+
+    let entries = tables.vehicles.len()
+        + tables.loadouts.len()
+        + tables.categories.len()
+        + tables.domains.len();
+    *TABLES.write().unwrap() = tables;
+    LOGGED_MISSES.lock().unwrap().clear();
+
+    Ok(entries)
+}
+
+fn load_file(path: &str) -> Result<Tables, String> {
+    let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path, e))
+}
+
+async fn load_postgres() -> Result<Tables, sqlx::Error> {
+    let pool = PG.get().await;
+
+    let timer = telemetry::db_read("translators", "load");
+    let rows = query("SELECT kind, id, name FROM translators;")
+        .fetch_all(pool)
+        .await?;
+    timer.observe_duration();
+
+    let mut tables = Tables::default();
+    for row in rows {
+        let kind: String = row.get("kind");
+        let table = match kind.as_str() {
+            "vehicle" => &mut tables.vehicles,
+            "loadout" => &mut tables.loadouts,
+            "category" => &mut tables.categories,
+            "domain" => &mut tables.domains,
+            _ => continue,
+        };
+        table.insert(row.get("id"), row.get("name"));
+    }
+
+    Ok(tables)
+}
+
+async fn load_and_log() {
+    match load().await {
+        Ok(entries) => println!("[translators] Loaded {} entries from {}", entries, *SOURCE),
+        Err(e) => println!("[translators] ERR => {}, keeping the previous tables", e),
+    }
+}
+
+/// Loads the tables, then reloads them every time we get a SIGHUP.
+pub async fn start() {
+    if SOURCE.is_empty() {
+        println!("[translators] TRANSLATORS not set, using compiled tables only");
+        return;
+    }
+
+    load_and_log().await;
+
+    tokio::spawn(async {
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                println!("[translators] Can't listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            println!("[translators] SIGHUP, reloading");
+            load_and_log().await;
+        }
+    });
+}
+
+/// POST /translators/reload
+pub async fn reload_handler(headers: HeaderMap) -> impl IntoResponse {
+    // Anyone who can reach the port could reload otherwise
+    if ADMIN_TOKEN.is_empty() {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "ADMIN_TOKEN not set, reloading over HTTP is off" })),
+        );
+    }
+
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value == format!("Bearer {}", *ADMIN_TOKEN))
+        .unwrap_or(false);
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "unauthorized" })),
+        );
+    }
+
+    if SOURCE.is_empty() {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "TRANSLATORS not set, nothing to reload" })),
+        );
+    }
+
+    match load().await {
+        Ok(entries) => (
+            StatusCode::OK,
+            Json(json!({ "source": *SOURCE, "entries": entries })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        ),
+    }
+}